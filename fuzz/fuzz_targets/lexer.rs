@@ -0,0 +1,13 @@
+//! Tokenizes arbitrary bytes, invalid UTF-8 is replaced first. Every span must slice the source.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let source = String::from_utf8_lossy(data);
+    let mut end = 0;
+    for (_, span) in amp::tokenize(&source) {
+        assert!(span.start >= end && span.start < span.end);
+        assert!(source.get(span.start..span.end).is_some());
+        end = span.end;
+    }
+});
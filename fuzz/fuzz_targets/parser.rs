@@ -0,0 +1,7 @@
+//! Parses arbitrary text, which may fail but must not panic.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|source: &str| {
+    let _ = amp::parse_program(source);
+});
@@ -0,0 +1,22 @@
+//! Every program that parses must format, and the formatted text must parse to the same tree,
+//! apart from source locations.
+#![no_main]
+use amp::visit::strip_locations;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|source: &str| {
+    let program = match amp::parse_program(source) {
+        Ok(program) => program,
+        Err(_) => return,
+    };
+    let formatted = match amp::formatter::format(source) {
+        Ok(formatted) => formatted,
+        Err(e) => panic!("failed to format {:?}: {}", source, e),
+    };
+    assert_eq!(
+        amp::parse_program(&formatted).map(strip_locations),
+        Ok(strip_locations(program)),
+        "formatted as {:?}",
+        formatted
+    );
+});
@@ -1,46 +1,173 @@
+//! Syntax tree and tokens.
+//!
+//! With the `serde` feature `Span`, `Location`, `Ident`, `Token`, `EKeyword`, `Statement` and
+//! `Expr` implement `Serialize` and `Deserialize`. Spans and identifiers are plain objects, a
+//! location is its span or `null`, keywords are their lowercase source text and every other enum
+//! is adjacently tagged: the variant name is stored under `type` and its contents, if any, under
+//! `data`. For example `let x = -1;` is
+//!
+//! ```json
+//! {"type": "Let", "data": {
+//!     "ident": {"name": "x", "location": {"start": 4, "end": 5, "line": 1, "column": 5}},
+//!     "value": {"type": "Prefix", "data": {
+//!         "prefix": {"type": "Minus"},
+//!         "value": {"type": "Const", "data": 1}}}}}
+//! ```
+//!
+//! Variant and field names follow the Rust definitions, so renaming either is a breaking change
+//! of the format. `dump::json` and `amp_cli ast --format=json` print this representation too.
 #![allow(dead_code)]
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Location of a token in the source text.
+///
+/// `start` and `end` are byte offsets, `line` and `column` are 1-based and point at `start`.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Where a node of the tree starts in the source, `None` for nodes that weren't parsed.
+///
+/// Trees parsed from sources that only differ in layout differ in their locations, use
+/// `visit::strip_locations` to compare just their shape.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Location(pub Option<Span>);
+
+impl From<Span> for Location {
+    fn from(span: Span) -> Location {
+        Location(Some(span))
+    }
+}
+
+/// A name in the tree: a variable, a `let` or `for` binding or a parameter.
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Ident {
+    pub name: String,
+    pub location: Location,
+}
+
+impl Ident {
+    /// An identifier without a location.
+    pub fn new(name: &str) -> Ident {
+        Ident {
+            name: name.to_string(),
+            location: Location::default(),
+        }
+    }
+}
+
+impl fmt::Display for Ident {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "data"))]
 pub enum Statement {
-    Let { ident: Token, value: Box<Expr> },
+    Let {
+        ident: Ident,
+        value: Box<Expr>,
+    },
     Expression(Box<Expr>),
-    Return { value: Box<Expr> },
+    Return {
+        value: Box<Expr>,
+    },
+    While {
+        condition: Box<Expr>,
+        body: Vec<Statement>,
+    },
+    /// `for (variable in iterable) { body }`, the variable is bound in the scope of the body.
+    For {
+        variable: Ident,
+        iterable: Box<Expr>,
+        body: Vec<Statement>,
+    },
+    /// `break`, located at the keyword.
+    Break(Location),
+    /// `continue`, located at the keyword.
+    Continue(Location),
+    /// The `;` after an expression that ends a block, so that the block evaluates to null.
     Empty,
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "data"))]
 pub enum Expr {
     Const(u64),
     String(String),
     Boolean(bool),
-    Ident(String),
+    Ident(Ident),
+    /// An identifier bound in a local scope, produced by the resolver. `depth` is the number of
+    /// scopes between the use and the binding, `slot` is the index of the binding in that scope.
+    Local {
+        name: Ident,
+        depth: usize,
+        slot: usize,
+    },
     Prefix {
         prefix: Token,
         value: Box<Expr>,
     },
+    /// A binary operation, located at its operator.
     Infix {
         left: Box<Expr>,
         operator: Token,
         right: Box<Expr>,
+        location: Location,
     },
+    /// An `if` expression, located at the `if` keyword.
     If {
         condition: Box<Expr>,
         consequence: Vec<Statement>,
         alternative: Vec<Statement>,
+        location: Location,
     },
     Function {
-        parameters: Vec<String>,
+        parameters: Vec<Ident>,
         body: Vec<Statement>,
     },
     Call {
         function: Box<Expr>,
         arguments: Vec<Expr>,
     },
+    Array(Vec<Expr>),
+    /// Key and value pairs in source order.
+    Hash(Vec<(Expr, Expr)>),
+    Index {
+        left: Box<Expr>,
+        index: Box<Expr>,
+    },
+    /// `{ statements }`, evaluated in a scope of its own. Its value is the value of the last
+    /// statement, which is null unless it is an expression without a trailing semicolon.
+    Block(Vec<Statement>),
+    /// `target = value` or a compound assignment like `target += value`. The target is an
+    /// identifier or an index expression whose innermost left side is an identifier.
+    Assign {
+        target: Box<Expr>,
+        operator: Token,
+        value: Box<Expr>,
+    },
     Unknown,
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum EKeyword {
+    #[cfg_attr(feature = "serde", serde(rename = "fn"))]
     Function,
     Let,
     If,
@@ -48,33 +175,58 @@ pub enum EKeyword {
     True,
     False,
     Return,
+    While,
+    Break,
+    Continue,
+    For,
+    In,
 }
 
-impl ToString for EKeyword {
-    fn to_string(&self) -> String {
-        match self {
-            EKeyword::Function => "fn".to_string(),
-            EKeyword::Let => "let".to_string(),
-            EKeyword::If => "if".to_string(),
-            EKeyword::Else => "else".to_string(),
-            EKeyword::True => "true".to_string(),
-            EKeyword::False => "false".to_string(),
-            EKeyword::Return => "return".to_string(),
-        }
+impl fmt::Display for EKeyword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kw = match self {
+            EKeyword::Function => "fn",
+            EKeyword::Let => "let",
+            EKeyword::If => "if",
+            EKeyword::Else => "else",
+            EKeyword::True => "true",
+            EKeyword::False => "false",
+            EKeyword::Return => "return",
+            EKeyword::While => "while",
+            EKeyword::Break => "break",
+            EKeyword::Continue => "continue",
+            EKeyword::For => "for",
+            EKeyword::In => "in",
+        };
+        f.write_str(kw)
     }
 }
 
 #[derive(PartialOrd, PartialEq)]
 pub enum Precedence {
     Lowest,
+    Assign,      // = or +=, right associative
+    Range,       // .. or ..=
+    Or,          // ||
+    And,         // &&
     Equals,      // ==
     LessGreater, // > or <
+    BitOr,       // |
+    BitXor,      // ^
+    BitAnd,      // &
+    Shift,       // << or >>
     Sum,         // +
-    Product,     // *
-    Prefix,      // -X or !X
+    Product,     // * or %
+    Prefix,      // -X, !X or ~X
+    Power,       // **, right associative and above Prefix, so -2 ** 2 is -4
+    Call,        // f(X)
+    Index,       // a[X]
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "data"))]
 pub enum Token {
     RightCurlyBrace,
     LeftCurlyBrace,
@@ -84,13 +236,26 @@ pub enum Token {
     LeftParenthesis,
     Comma,
     SemiColon,
+    Colon,
 
     // Operators
     Assign,
+    PlusAssign,
+    MinusAssign,
+    AsteriskAssign,
+    SlashAssign,
     Plus,
     Minus,
     Asterisk,
     Slash,
+    Percent,
+    Power,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
+    ShiftLeft,
+    ShiftRight,
     Bang,
     LessThan,
     GreaterThan,
@@ -98,44 +263,82 @@ pub enum Token {
     NotEqual,
     LessThanOrEqual,
     GreaterThanOrEqual,
+    DotDot,
+    DotDotEqual,
+    And,
+    Or,
 
     EOF,
     Null,
     Invalid(String),
+    Comment(String),
 
     Integer(u64),
+    String(String),
     Identifier(String),
     Keyword(EKeyword),
 }
-impl ToString for Token {
-    fn to_string(&self) -> String {
-        match self.clone() {
-            Token::RightCurlyBrace => "}".to_string(),
-            Token::LeftCurlyBrace => "{".to_string(),
-            Token::RightSquareBrace => "]".to_string(),
-            Token::LeftSquareBrace => "[".to_string(),
-            Token::RightParenthesis => ")".to_string(),
-            Token::LeftParenthesis => "(".to_string(),
-            Token::Comma => ",".to_string(),
-            Token::SemiColon => ";".to_string(),
-            Token::Assign => "=".to_string(),
-            Token::Plus => "+".to_string(),
-            Token::Minus => "-".to_string(),
-            Token::Asterisk => "*".to_string(),
-            Token::Slash => "/".to_string(),
-            Token::Bang => "!".to_string(),
-            Token::LessThan => "<".to_string(),
-            Token::GreaterThan => ">".to_string(),
-            Token::Equal => "==".to_string(),
-            Token::NotEqual => "!=".to_string(),
-            Token::LessThanOrEqual => "<=".to_string(),
-            Token::GreaterThanOrEqual => ">=".to_string(),
-            Token::Integer(n) => n.to_string(),
-            Token::Identifier(id) => id,
-            Token::Keyword(kw) => kw.to_string(),
-            Token::EOF => "".to_string(),
-            Token::Invalid(s) => format!("<invalid=\"{}\"", s),
-            Token::Null => "".to_string(),
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::RightCurlyBrace => f.write_str("}"),
+            Token::LeftCurlyBrace => f.write_str("{"),
+            Token::RightSquareBrace => f.write_str("]"),
+            Token::LeftSquareBrace => f.write_str("["),
+            Token::RightParenthesis => f.write_str(")"),
+            Token::LeftParenthesis => f.write_str("("),
+            Token::Comma => f.write_str(","),
+            Token::SemiColon => f.write_str(";"),
+            Token::Colon => f.write_str(":"),
+            Token::Assign => f.write_str("="),
+            Token::PlusAssign => f.write_str("+="),
+            Token::MinusAssign => f.write_str("-="),
+            Token::AsteriskAssign => f.write_str("*="),
+            Token::SlashAssign => f.write_str("/="),
+            Token::Plus => f.write_str("+"),
+            Token::Minus => f.write_str("-"),
+            Token::Asterisk => f.write_str("*"),
+            Token::Slash => f.write_str("/"),
+            Token::Percent => f.write_str("%"),
+            Token::Power => f.write_str("**"),
+            Token::Ampersand => f.write_str("&"),
+            Token::Pipe => f.write_str("|"),
+            Token::Caret => f.write_str("^"),
+            Token::Tilde => f.write_str("~"),
+            Token::ShiftLeft => f.write_str("<<"),
+            Token::ShiftRight => f.write_str(">>"),
+            Token::Bang => f.write_str("!"),
+            Token::LessThan => f.write_str("<"),
+            Token::GreaterThan => f.write_str(">"),
+            Token::Equal => f.write_str("=="),
+            Token::NotEqual => f.write_str("!="),
+            Token::LessThanOrEqual => f.write_str("<="),
+            Token::GreaterThanOrEqual => f.write_str(">="),
+            Token::DotDot => f.write_str(".."),
+            Token::DotDotEqual => f.write_str("..="),
+            Token::And => f.write_str("&&"),
+            Token::Or => f.write_str("||"),
+            Token::Integer(n) => write!(f, "{}", n),
+            Token::String(s) => {
+                f.write_str("\"")?;
+                for ch in s.chars() {
+                    match ch {
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        '\n' => f.write_str("\\n")?,
+                        '\t' => f.write_str("\\t")?,
+                        '\r' => f.write_str("\\r")?,
+                        ch => write!(f, "{}", ch)?,
+                    }
+                }
+                f.write_str("\"")
+            }
+            Token::Identifier(id) => f.write_str(id),
+            Token::Keyword(kw) => write!(f, "{}", kw),
+            Token::EOF => Ok(()),
+            Token::Invalid(s) => write!(f, "<invalid=\"{}\"", s),
+            Token::Comment(s) => write!(f, "//{}", s),
+            Token::Null => Ok(()),
         }
     }
 }
@@ -149,6 +352,7 @@ impl Token {
             '(' => Some(Token::LeftParenthesis),
             ')' => Some(Token::RightParenthesis),
             ',' => Some(Token::Comma),
+            ':' => Some(Token::Colon),
             '=' => Some(Token::Assign),
             '+' => Some(Token::Plus),
             ';' => Some(Token::SemiColon),
@@ -158,6 +362,11 @@ impl Token {
             '<' => Some(Token::LessThan),
             '>' => Some(Token::GreaterThan),
             '-' => Some(Token::Minus),
+            '%' => Some(Token::Percent),
+            '&' => Some(Token::Ampersand),
+            '|' => Some(Token::Pipe),
+            '^' => Some(Token::Caret),
+            '~' => Some(Token::Tilde),
             _ => None,
         }
     }
@@ -170,15 +379,121 @@ impl Token {
             Token::Equal => Precedence::Equals,
             Token::NotEqual => Precedence::Equals,
             Token::Asterisk => Precedence::Product,
+            Token::Percent => Precedence::Product,
+            Token::Power => Precedence::Power,
+            Token::Ampersand => Precedence::BitAnd,
+            Token::Pipe => Precedence::BitOr,
+            Token::Caret => Precedence::BitXor,
+            Token::ShiftLeft => Precedence::Shift,
+            Token::ShiftRight => Precedence::Shift,
             Token::LessThan => Precedence::LessGreater,
             Token::GreaterThan => Precedence::LessGreater,
             Token::LessThanOrEqual => Precedence::LessGreater,
             Token::GreaterThanOrEqual => Precedence::LessGreater,
+            Token::Assign | Token::PlusAssign | Token::MinusAssign | Token::AsteriskAssign | Token::SlashAssign => {
+                Precedence::Assign
+            }
+            Token::DotDot => Precedence::Range,
+            Token::DotDotEqual => Precedence::Range,
+            Token::Or => Precedence::Or,
+            Token::And => Precedence::And,
+            Token::LeftParenthesis => Precedence::Call,
+            Token::LeftSquareBrace => Precedence::Index,
             _ => Precedence::Lowest,
         }
     }
 
+    /// The binary operator applied by a compound assignment, e.g. `+` for `+=`.
+    pub fn compound_operator(&self) -> Option<Token> {
+        match self {
+            Token::PlusAssign => Some(Token::Plus),
+            Token::MinusAssign => Some(Token::Minus),
+            Token::AsteriskAssign => Some(Token::Asterisk),
+            Token::SlashAssign => Some(Token::Slash),
+            _ => None,
+        }
+    }
+
     pub fn literal(&self) -> String {
         self.to_string()
     }
 }
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use crate::parse_program;
+    use serde_json::json;
+
+    fn round_trip<T>(value: &T) -> T
+    where
+        T: Serialize + for<'de> Deserialize<'de>,
+    {
+        serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn uses_documented_representation() {
+        let program = parse_program("let x = -1;").unwrap();
+        assert_eq!(
+            serde_json::to_value(&program).unwrap(),
+            json!([{"type": "Let", "data": {
+                "ident": {"name": "x", "location": {"start": 4, "end": 5, "line": 1, "column": 5}},
+                "value": {"type": "Prefix", "data": {
+                    "prefix": {"type": "Minus"},
+                    "value": {"type": "Const", "data": 1}}}}}])
+        );
+        assert_eq!(
+            serde_json::to_value(Token::Keyword(EKeyword::Function)).unwrap(),
+            json!({"type": "Keyword", "data": "fn"})
+        );
+        assert_eq!(
+            serde_json::to_value(Span {
+                start: 1,
+                end: 3,
+                line: 1,
+                column: 2
+            })
+            .unwrap(),
+            json!({"start": 1, "end": 3, "line": 1, "column": 2})
+        );
+    }
+
+    #[test]
+    fn round_trips_programs_and_tokens() {
+        let program = parse_program(
+            "let add = fn(a, b) { return a + b; };
+if (add(1, 2) >= 3) { true } else { !false };;",
+        )
+        .unwrap();
+        assert_eq!(round_trip(&program), program);
+
+        let mut program = program;
+        crate::resolver::resolve(&mut program);
+        assert_eq!(round_trip(&program), program);
+
+        let ident = Ident {
+            name: "x".to_string(),
+            location: Span {
+                start: 4,
+                end: 5,
+                line: 1,
+                column: 5,
+            }
+            .into(),
+        };
+        assert_eq!(round_trip(&ident), ident);
+        assert_eq!(
+            round_trip(&Statement::Break(Location::default())),
+            Statement::Break(Location(None))
+        );
+
+        let tokens = crate::tokenize("let x = 5; // five\n$");
+        assert_eq!(round_trip(&tokens), tokens);
+        assert_eq!(
+            round_trip(&Expr::String("s".to_string())),
+            Expr::String("s".to_string())
+        );
+        assert_eq!(round_trip(&Token::EOF), Token::EOF);
+    }
+}
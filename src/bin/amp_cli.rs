@@ -1,6 +1,297 @@
-use amp;
+use amp::{
+    ast::Statement,
+    dump,
+    eval::Evaluator,
+    formatter,
+    lint::{self, Level, LintConfig},
+    parse_program, resolver, tokenize,
+    value::Value,
+};
+use std::{
+    fs,
+    io::{self, Read},
+    process,
+    rc::Rc,
+};
+
+const USAGE: &str = "usage:
+    amp_cli                                      start the interactive shell
+    amp_cli run <file> [args...]                 run a program, its arguments are bound to `args`
+    amp_cli check <file>                         parse and resolve a program without running it
+    amp_cli tokens <file>                        print the tokens of a program
+    amp_cli ast [--format=debug|json|sexpr] <file>
+                                                 print the syntax tree of a program, json is the
+                                                 serde representation and needs the serde feature
+    amp_cli lint [-A|-W|-D <rule>]... <file>...  check files with the linter
+    amp_cli fmt [--check] <file>...              format files in place or only check them
+
+<file> may be '-' to read the program from stdin.
+
+exit codes:
+    0  success
+    1  runtime error, lint or format failure
+    2  usage or I/O error
+    3  parse or resolution error";
+
+/// Global holding the arguments of `run`.
+const SCRIPT_ARGS: &str = "args";
+
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_SYNTAX: i32 = 3;
 
 fn main() {
     env_logger::builder().format_module_path(false).init();
-    amp::interactive::AmpCli::run()
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        None => amp::interactive::AmpCli::run(),
+        Some("run") => process::exit(run_run(&args[1..])),
+        Some("check") => process::exit(run_check(&args[1..])),
+        Some("tokens") => process::exit(run_tokens(&args[1..])),
+        Some("ast") => process::exit(run_ast(&args[1..])),
+        Some("lint") => process::exit(run_lint(&args[1..])),
+        Some("fmt") => process::exit(run_fmt(&args[1..])),
+        Some(command) => {
+            eprintln!("unknown command '{}'\n{}", command, USAGE);
+            process::exit(EXIT_USAGE);
+        }
+    }
+}
+
+/// Reads the program named by `file`, `-` is stdin.
+fn read_source(file: &str) -> io::Result<String> {
+    if file == "-" {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source)?;
+        Ok(source)
+    } else {
+        fs::read_to_string(file)
+    }
+}
+
+/// Reads the single file argument of `run`, `check`, `tokens` and `ast`.
+fn read_single(args: &[&String]) -> Result<(String, String), i32> {
+    let file = match args {
+        [file] => file.as_str(),
+        _ => {
+            eprintln!("{}", USAGE);
+            return Err(EXIT_USAGE);
+        }
+    };
+    match read_source(file) {
+        Ok(source) => Ok((file.to_string(), source)),
+        Err(e) => {
+            eprintln!("{}: {}", file, e);
+            Err(EXIT_USAGE)
+        }
+    }
+}
+
+/// Prints the parse and resolution errors of `source`, returns whether there were any.
+fn report_syntax_errors(file: &str, source: &str) -> bool {
+    let mut failed = false;
+    for diagnostic in resolver::diagnostics(source, &[SCRIPT_ARGS]) {
+        if diagnostic.is_error() {
+            eprintln!("{}:{}", file, diagnostic);
+            failed = true;
+        }
+    }
+    failed
+}
+
+fn run_run(args: &[String]) -> i32 {
+    let (file, source) = match read_single(&args.iter().take(1).collect::<Vec<_>>()) {
+        Ok(input) => input,
+        Err(code) => return code,
+    };
+    if report_syntax_errors(&file, &source) {
+        return EXIT_SYNTAX;
+    }
+
+    let mut evaluator = Evaluator::new();
+    let script_args = args[1..].iter().map(|arg| Value::String(arg.clone())).collect();
+    evaluator.define(SCRIPT_ARGS, Value::Array(Rc::new(script_args)));
+    match evaluator.eval(&source) {
+        Ok(_) => EXIT_SUCCESS,
+        Err(e) => {
+            eprintln!("{}: runtime error: {}", file, e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+fn run_check(args: &[String]) -> i32 {
+    let (file, source) = match read_single(&args.iter().collect::<Vec<_>>()) {
+        Ok(input) => input,
+        Err(code) => return code,
+    };
+    if report_syntax_errors(&file, &source) {
+        EXIT_SYNTAX
+    } else {
+        EXIT_SUCCESS
+    }
+}
+
+fn run_tokens(args: &[String]) -> i32 {
+    let (_, source) = match read_single(&args.iter().collect::<Vec<_>>()) {
+        Ok(input) => input,
+        Err(code) => return code,
+    };
+    for (token, span) in tokenize(&source) {
+        println!("{}:{}\t{:?}", span.line, span.column, token);
+    }
+    EXIT_SUCCESS
+}
+
+fn run_ast(args: &[String]) -> i32 {
+    let mut format = "debug";
+    let mut files = Vec::new();
+    for arg in args {
+        match arg.strip_prefix("--format=") {
+            Some(value) => format = value,
+            None => files.push(arg),
+        }
+    }
+    if !matches!(format, "debug" | "json" | "sexpr") {
+        eprintln!("unknown ast format '{}'\n{}", format, USAGE);
+        return EXIT_USAGE;
+    }
+    let (file, source) = match read_single(&files) {
+        Ok(input) => input,
+        Err(code) => return code,
+    };
+
+    let program = match parse_program(&source) {
+        Ok(program) => program,
+        Err(_) => {
+            report_syntax_errors(&file, &source);
+            return EXIT_SYNTAX;
+        }
+    };
+    match format {
+        "json" => return print_json(&program),
+        "sexpr" => println!("{}", dump::sexpr(&program)),
+        _ => println!("{:#?}", program),
+    }
+    EXIT_SUCCESS
+}
+
+#[cfg(feature = "serde")]
+fn print_json(program: &[Statement]) -> i32 {
+    println!("{:#}", dump::json(program));
+    EXIT_SUCCESS
+}
+
+#[cfg(not(feature = "serde"))]
+fn print_json(_: &[Statement]) -> i32 {
+    eprintln!("json output needs amp_cli built with the serde feature");
+    EXIT_USAGE
+}
+
+fn run_lint(args: &[String]) -> i32 {
+    let mut config = LintConfig::new();
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let level = match arg.as_str() {
+            "-A" | "--allow" => Level::Allow,
+            "-W" | "--warn" => Level::Warn,
+            "-D" | "--deny" => Level::Deny,
+            _ => {
+                files.push(arg);
+                continue;
+            }
+        };
+        let rule = match args.next() {
+            Some(rule) => rule,
+            None => {
+                eprintln!("missing rule name after '{}'\n{}", arg, USAGE);
+                return EXIT_USAGE;
+            }
+        };
+        if let Err(e) = config.set(rule, level) {
+            eprintln!("{}", e);
+            return EXIT_USAGE;
+        }
+    }
+    if files.is_empty() {
+        eprintln!("{}", USAGE);
+        return EXIT_USAGE;
+    }
+
+    let mut failed = false;
+    for file in files {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                failed = true;
+                continue;
+            }
+        };
+        match lint::lint(&source, &config) {
+            Ok(diagnostics) => {
+                for diagnostic in diagnostics {
+                    failed |= diagnostic.is_error();
+                    println!("{}:{}", file, diagnostic);
+                }
+            }
+            Err(e) => {
+                println!("{}: error: {}", file, e);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        EXIT_FAILURE
+    } else {
+        EXIT_SUCCESS
+    }
+}
+
+fn run_fmt(args: &[String]) -> i32 {
+    let check = args.iter().any(|arg| arg == "--check");
+    let files: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
+    if files.is_empty() {
+        eprintln!("{}", USAGE);
+        return EXIT_USAGE;
+    }
+
+    let mut failed = false;
+    for file in files {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                failed = true;
+                continue;
+            }
+        };
+        let formatted = match formatter::format(&source) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("{}: error: {}", file, e);
+                failed = true;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", file);
+            failed = true;
+        } else if let Err(e) = fs::write(file, formatted) {
+            eprintln!("{}: {}", file, e);
+            failed = true;
+        }
+    }
+
+    if failed {
+        EXIT_FAILURE
+    } else {
+        EXIT_SUCCESS
+    }
 }
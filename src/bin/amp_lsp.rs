@@ -0,0 +1,15 @@
+use std::{io, process};
+
+fn main() {
+    env_logger::builder().format_module_path(false).init();
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut server = amp::lsp::Server::new(stdin.lock(), stdout.lock());
+    match server.run() {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            eprintln!("amp-lsp: {}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,378 @@
+//! Functions available to every program.
+//!
+//! Builtins are looked up after globals, so a program can shadow them with its own `let`. Each
+//! one checks its arguments and reports problems with `AmpError::ArgumentCount` or
+//! `AmpError::InvalidArgument`, which name the builtin and the 1-based argument position.
+//! Builtins that create strings, arrays or hashes account for them with `Evaluator::allocate`.
+use super::{
+    eval::Evaluator,
+    value::{Builtin, Value},
+    AmpError,
+};
+use std::{ops::RangeInclusive, rc::Rc};
+
+type Function = fn(&mut Evaluator, &[Value]) -> Result<Value, AmpError>;
+
+/// Longest array `range` creates. Without a memory limit a huge length would otherwise abort the
+/// process when the array is allocated.
+const MAX_RANGE_LEN: i128 = 1 << 24;
+
+const BUILTINS: &[(&str, Function)] = &[
+    ("len", len),
+    ("puts", puts),
+    ("print", print),
+    ("first", first),
+    ("last", last),
+    ("rest", rest),
+    ("push", push),
+    ("keys", keys),
+    ("values", values),
+    ("type", type_of),
+    ("str", str),
+    ("int", int),
+    ("range", range),
+    ("min", min),
+    ("max", max),
+    ("abs", abs),
+];
+
+pub fn is_builtin(name: &str) -> bool {
+    BUILTINS.iter().any(|(builtin, _)| *builtin == name)
+}
+
+/// All builtins as values, keyed by name.
+pub(crate) fn all() -> Vec<(&'static str, Value)> {
+    BUILTINS
+        .iter()
+        .map(|&(name, function)| (name, Value::Builtin(Rc::new(Builtin::new(name, function)))))
+        .collect()
+}
+
+/// Checks that `arguments` has an allowed length.
+pub fn check_arity(name: &str, arguments: &[Value], allowed: RangeInclusive<usize>) -> Result<(), AmpError> {
+    if allowed.contains(&arguments.len()) {
+        return Ok(());
+    }
+    let noun = |n: usize| if n == 1 { "argument" } else { "arguments" };
+    let (start, end) = (*allowed.start(), *allowed.end());
+    let expected = if start == end {
+        format!("{} {}", start, noun(start))
+    } else if end == usize::MAX {
+        format!("at least {} {}", start, noun(start))
+    } else {
+        format!("{} to {} arguments", start, end)
+    };
+    Err(AmpError::ArgumentCount {
+        name: name.to_string(),
+        expected,
+        got: arguments.len(),
+    })
+}
+
+/// Error for the argument at `index` (0-based) not being one of the `expected` types.
+pub fn type_error(name: &str, index: usize, expected: &str, got: &Value) -> AmpError {
+    AmpError::InvalidArgument {
+        name: name.to_string(),
+        position: index + 1,
+        message: format!("expected {}, got {}", expected, got.type_name()),
+    }
+}
+
+fn integer(name: &str, arguments: &[Value], index: usize) -> Result<i64, AmpError> {
+    match &arguments[index] {
+        Value::Integer(n) => Ok(*n),
+        other => Err(type_error(name, index, "integer", other)),
+    }
+}
+
+fn array<'a>(name: &str, arguments: &'a [Value], index: usize) -> Result<&'a Rc<Vec<Value>>, AmpError> {
+    match &arguments[index] {
+        Value::Array(values) => Ok(values),
+        other => Err(type_error(name, index, "array", other)),
+    }
+}
+
+/// Text of a value as printed by `puts` and returned by `str`, strings are not quoted.
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn len(_: &mut Evaluator, arguments: &[Value]) -> Result<Value, AmpError> {
+    check_arity("len", arguments, 1..=1)?;
+    let len = match &arguments[0] {
+        Value::String(s) => s.chars().count(),
+        Value::Array(values) => values.len(),
+        Value::Hash(entries) => entries.len(),
+        other => return Err(type_error("len", 0, "string, array or hash", other)),
+    };
+    Ok(Value::Integer(len as i64))
+}
+
+/// Prints every argument on its own line.
+fn puts(evaluator: &mut Evaluator, arguments: &[Value]) -> Result<Value, AmpError> {
+    for argument in arguments {
+        writeln!(evaluator.output(), "{}", text(argument)).map_err(|e| AmpError::Io(e.to_string()))?;
+    }
+    Ok(Value::Null)
+}
+
+/// Prints the arguments separated by spaces, without a trailing newline.
+fn print(evaluator: &mut Evaluator, arguments: &[Value]) -> Result<Value, AmpError> {
+    let line: Vec<String> = arguments.iter().map(text).collect();
+    write!(evaluator.output(), "{}", line.join(" ")).map_err(|e| AmpError::Io(e.to_string()))?;
+    Ok(Value::Null)
+}
+
+fn first(_: &mut Evaluator, arguments: &[Value]) -> Result<Value, AmpError> {
+    check_arity("first", arguments, 1..=1)?;
+    Ok(array("first", arguments, 0)?.first().cloned().unwrap_or(Value::Null))
+}
+
+fn last(_: &mut Evaluator, arguments: &[Value]) -> Result<Value, AmpError> {
+    check_arity("last", arguments, 1..=1)?;
+    Ok(array("last", arguments, 0)?.last().cloned().unwrap_or(Value::Null))
+}
+
+/// All elements but the first, `null` for an empty array.
+fn rest(evaluator: &mut Evaluator, arguments: &[Value]) -> Result<Value, AmpError> {
+    check_arity("rest", arguments, 1..=1)?;
+    let values = array("rest", arguments, 0)?;
+    if values.is_empty() {
+        return Ok(Value::Null);
+    }
+    evaluator.allocate(values.len() - 1)?;
+    Ok(Value::Array(Rc::new(values[1..].to_vec())))
+}
+
+/// Returns a new array with the second argument appended.
+fn push(evaluator: &mut Evaluator, arguments: &[Value]) -> Result<Value, AmpError> {
+    check_arity("push", arguments, 2..=2)?;
+    let values = array("push", arguments, 0)?;
+    evaluator.allocate(values.len() + 1)?;
+    let mut values = values.as_ref().clone();
+    values.push(arguments[1].clone());
+    Ok(Value::Array(Rc::new(values)))
+}
+
+fn keys(evaluator: &mut Evaluator, arguments: &[Value]) -> Result<Value, AmpError> {
+    check_arity("keys", arguments, 1..=1)?;
+    match &arguments[0] {
+        Value::Hash(entries) => {
+            evaluator.allocate(entries.len())?;
+            Ok(Value::Array(Rc::new(
+                entries.keys().cloned().map(Value::from).collect(),
+            )))
+        }
+        other => Err(type_error("keys", 0, "hash", other)),
+    }
+}
+
+fn values(evaluator: &mut Evaluator, arguments: &[Value]) -> Result<Value, AmpError> {
+    check_arity("values", arguments, 1..=1)?;
+    match &arguments[0] {
+        Value::Hash(entries) => {
+            evaluator.allocate(entries.len())?;
+            Ok(Value::Array(Rc::new(entries.values().cloned().collect())))
+        }
+        other => Err(type_error("values", 0, "hash", other)),
+    }
+}
+
+fn type_of(_: &mut Evaluator, arguments: &[Value]) -> Result<Value, AmpError> {
+    check_arity("type", arguments, 1..=1)?;
+    Ok(Value::String(arguments[0].type_name().to_string()))
+}
+
+fn str(evaluator: &mut Evaluator, arguments: &[Value]) -> Result<Value, AmpError> {
+    check_arity("str", arguments, 1..=1)?;
+    let text = text(&arguments[0]);
+    evaluator.allocate(text.len())?;
+    Ok(Value::String(text))
+}
+
+/// Converts a decimal string or a boolean to an integer.
+fn int(_: &mut Evaluator, arguments: &[Value]) -> Result<Value, AmpError> {
+    check_arity("int", arguments, 1..=1)?;
+    match &arguments[0] {
+        Value::Integer(n) => Ok(Value::Integer(*n)),
+        Value::Boolean(b) => Ok(Value::Integer(*b as i64)),
+        Value::String(s) => s
+            .trim()
+            .parse()
+            .map(Value::Integer)
+            .map_err(|_| AmpError::InvalidArgument {
+                name: "int".to_string(),
+                position: 1,
+                message: format!("{:?} is not an integer", s),
+            }),
+        other => Err(type_error("int", 0, "integer, boolean or string", other)),
+    }
+}
+
+/// `range(end)` or `range(start, end)`, the integers from `start` (default 0) up to but without
+/// `end`. Longer ranges than `MAX_RANGE_LEN` are an error, `start..end` iterates them lazily.
+fn range(evaluator: &mut Evaluator, arguments: &[Value]) -> Result<Value, AmpError> {
+    check_arity("range", arguments, 1..=2)?;
+    let (start, end) = match arguments.len() {
+        1 => (0, integer("range", arguments, 0)?),
+        _ => (integer("range", arguments, 0)?, integer("range", arguments, 1)?),
+    };
+    let len = (i128::from(end) - i128::from(start)).max(0);
+    if len > MAX_RANGE_LEN {
+        return Err(AmpError::InvalidArgument {
+            name: "range".to_string(),
+            position: arguments.len(),
+            message: format!("{} integers are more than the maximum of {}", len, MAX_RANGE_LEN),
+        });
+    }
+    evaluator.allocate(len as usize)?;
+    Ok(Value::Array(Rc::new((start..end).map(Value::Integer).collect())))
+}
+
+/// Integers to compare for `min` and `max`, either the arguments or the elements of a single array.
+fn comparable(name: &str, arguments: &[Value]) -> Result<Vec<i64>, AmpError> {
+    check_arity(name, arguments, 1..=usize::MAX)?;
+    let numbers = match arguments {
+        [Value::Array(values)] => values
+            .iter()
+            .map(|value| match value {
+                Value::Integer(n) => Ok(*n),
+                other => Err(type_error(name, 0, "array of integers", other)),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => (0..arguments.len())
+            .map(|i| integer(name, arguments, i))
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+    if numbers.is_empty() {
+        return Err(AmpError::InvalidArgument {
+            name: name.to_string(),
+            position: 1,
+            message: "expected a non-empty array".to_string(),
+        });
+    }
+    Ok(numbers)
+}
+
+fn min(_: &mut Evaluator, arguments: &[Value]) -> Result<Value, AmpError> {
+    let numbers = comparable("min", arguments)?;
+    Ok(Value::Integer(numbers.into_iter().min().unwrap_or_default()))
+}
+
+fn max(_: &mut Evaluator, arguments: &[Value]) -> Result<Value, AmpError> {
+    let numbers = comparable("max", arguments)?;
+    Ok(Value::Integer(numbers.into_iter().max().unwrap_or_default()))
+}
+
+fn abs(_: &mut Evaluator, arguments: &[Value]) -> Result<Value, AmpError> {
+    check_arity("abs", arguments, 1..=1)?;
+    integer("abs", arguments, 0)?
+        .checked_abs()
+        .map(Value::Integer)
+        .ok_or(AmpError::IntegerOverflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        cell::RefCell,
+        io::{self, Write},
+    };
+
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn eval(source: &str) -> Result<String, AmpError> {
+        Evaluator::new().eval(source).map(|value| value.to_string())
+    }
+
+    #[test]
+    fn evaluates_builtins() {
+        assert_eq!(
+            eval(r#"[len("zażółć"), len([1, 2]), len({1: 2})]"#),
+            Ok("[6, 2, 1]".to_string())
+        );
+        assert_eq!(
+            eval("let a = [1, 2, 3]; [first(a), last(a), rest(a), push(a, 4), a]"),
+            Ok("[1, 3, [2, 3], [1, 2, 3, 4], [1, 2, 3]]".to_string())
+        );
+        assert_eq!(
+            eval("[first([]), last([]), rest([])]"),
+            Ok("[null, null, null]".to_string())
+        );
+        assert_eq!(
+            eval(r#"let h = {"b": 2, "a": 1}; [keys(h), values(h)]"#),
+            Ok(r#"[["a", "b"], [1, 2]]"#.to_string())
+        );
+        assert_eq!(
+            eval(r#"[type(1), type("s"), type(len), str(12), str("s"), int(" -7 "), int(true)]"#),
+            Ok(r#"["integer", "string", "function", "12", "s", -7, 1]"#.to_string())
+        );
+        assert_eq!(
+            eval("[range(3), range(2, 4), range(3, 1)]"),
+            Ok("[[0, 1, 2], [2, 3], []]".to_string())
+        );
+        assert_eq!(
+            eval("[min(3, -1, 2), max([4, 9]), abs(-5)]"),
+            Ok("[-1, 9, 5]".to_string())
+        );
+        assert_eq!(eval("let len = fn(x) { 0 }; len([1])"), Ok("0".to_string()));
+    }
+
+    #[test]
+    fn prints_values() {
+        let output = Output::default();
+        let mut evaluator = Evaluator::with_output(Box::new(output.clone()));
+        evaluator
+            .eval(r#"puts("a", 1, [true, "b"]); print("x", 2); print()"#)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output.0.borrow().clone()).unwrap(),
+            "a\n1\n[true, \"b\"]\nx 2"
+        );
+    }
+
+    #[test]
+    fn checks_arguments() {
+        assert_eq!(
+            eval("len(1)").unwrap_err().to_string(),
+            "invalid argument 1 to `len`: expected string, array or hash, got integer"
+        );
+        assert_eq!(
+            eval("push([], 1, 2)").unwrap_err().to_string(),
+            "`push` expects 2 arguments, got 3"
+        );
+        assert_eq!(
+            eval("range(1, true)").unwrap_err().to_string(),
+            "invalid argument 2 to `range`: expected integer, got boolean"
+        );
+        assert_eq!(
+            eval("min()").unwrap_err().to_string(),
+            "`min` expects at least 1 argument, got 0"
+        );
+        assert_eq!(
+            eval(r#"int("x")"#).unwrap_err().to_string(),
+            r#"invalid argument 1 to `int`: "x" is not an integer"#
+        );
+        assert_eq!(eval("abs(-9223372036854775807 - 1)"), Err(AmpError::IntegerOverflow));
+        assert_eq!(
+            eval("range(9223372036854775807)").unwrap_err().to_string(),
+            "invalid argument 1 to `range`: 9223372036854775807 integers are more than the maximum of 16777216"
+        );
+        assert!(eval("range(-9223372036854775807 - 1, 9223372036854775807)").is_err());
+    }
+}
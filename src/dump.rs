@@ -0,0 +1,152 @@
+//! Printable representations of syntax trees for tooling.
+//!
+//! `sexpr` renders a program as one s-expression per statement, e.g. `(let x (+ 1 2))`. With the
+//! `serde` feature `json` renders it in the serde representation of the `ast` module, which is the
+//! only JSON format of the tree.
+use super::ast::{Expr, Ident, Statement};
+#[cfg(feature = "serde")]
+use serde_json::Value;
+
+pub fn sexpr(program: &[Statement]) -> String {
+    let statements: Vec<String> = program.iter().map(statement_sexpr).collect();
+    statements.join("\n")
+}
+
+fn block_sexpr(head: &str, block: &[Statement]) -> String {
+    let mut out = format!("({}", head);
+    for statement in block {
+        out.push(' ');
+        out.push_str(&statement_sexpr(statement));
+    }
+    out.push(')');
+    out
+}
+
+fn statement_sexpr(statement: &Statement) -> String {
+    match statement {
+        Statement::Let { ident, value } => format!("(let {} {})", ident, expr_sexpr(value)),
+        Statement::Expression(expr) => expr_sexpr(expr),
+        Statement::Return { value } => format!("(return {})", expr_sexpr(value)),
+        Statement::While { condition, body } => {
+            format!("(while {} {})", expr_sexpr(condition), block_sexpr("do", body))
+        }
+        Statement::For {
+            variable,
+            iterable,
+            body,
+        } => format!(
+            "(for {} {} {})",
+            variable,
+            expr_sexpr(iterable),
+            block_sexpr("do", body)
+        ),
+        Statement::Break(_) => "(break)".to_string(),
+        Statement::Continue(_) => "(continue)".to_string(),
+        Statement::Empty => "()".to_string(),
+    }
+}
+
+fn expr_sexpr(expr: &Expr) -> String {
+    match expr {
+        Expr::Const(n) => n.to_string(),
+        Expr::String(s) => format!("{:?}", s),
+        Expr::Boolean(b) => b.to_string(),
+        Expr::Ident(ident) => ident.to_string(),
+        Expr::Local { name, depth, slot } => format!("{}@{}:{}", name, depth, slot),
+        Expr::Prefix { prefix, value } => format!("({} {})", prefix, expr_sexpr(value)),
+        Expr::Infix {
+            left, operator, right, ..
+        } => {
+            format!("({} {} {})", operator, expr_sexpr(left), expr_sexpr(right))
+        }
+        Expr::If {
+            condition,
+            consequence,
+            alternative,
+            ..
+        } => format!(
+            "(if {} {} {})",
+            expr_sexpr(condition),
+            block_sexpr("do", consequence),
+            block_sexpr("do", alternative)
+        ),
+        Expr::Function { parameters, body } => {
+            let parameters: Vec<String> = parameters.iter().map(Ident::to_string).collect();
+            format!("(fn ({}) {})", parameters.join(" "), block_sexpr("do", body))
+        }
+        Expr::Block(body) => block_sexpr("do", body),
+        Expr::Call { function, arguments } => {
+            let mut out = format!("({}", expr_sexpr(function));
+            for argument in arguments {
+                out.push(' ');
+                out.push_str(&expr_sexpr(argument));
+            }
+            out.push(')');
+            out
+        }
+        Expr::Array(elements) => {
+            let elements: Vec<String> = elements.iter().map(expr_sexpr).collect();
+            format!("[{}]", elements.join(" "))
+        }
+        Expr::Hash(pairs) => {
+            let pairs: Vec<String> = pairs
+                .iter()
+                .map(|(key, value)| format!("({} {})", expr_sexpr(key), expr_sexpr(value)))
+                .collect();
+            format!("{{{}}}", pairs.join(" "))
+        }
+        Expr::Index { left, index } => format!("(index {} {})", expr_sexpr(left), expr_sexpr(index)),
+        Expr::Assign {
+            target,
+            operator,
+            value,
+        } => format!("({} {} {})", operator, expr_sexpr(target), expr_sexpr(value)),
+        Expr::Unknown => "?".to_string(),
+    }
+}
+
+/// Renders `program` in the serde representation documented in the `ast` module.
+#[cfg(feature = "serde")]
+pub fn json(program: &[Statement]) -> Value {
+    serde_json::to_value(program).expect("syntax trees serialize to JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_program;
+
+    #[test]
+    fn prints_sexpr() {
+        let program = parse_program(
+            "let add = fn(a, b) { return a + b; };
+if (add(1, -2) < 3) { true } else { false }",
+        )
+        .unwrap();
+        assert_eq!(
+            sexpr(&program),
+            "(let add (fn (a b) (do (return (+ a b)))))
+(if (< (add 1 (- 2)) 3) (do true) (do false))"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn prints_json() {
+        let program = parse_program("let x = f(1) * 2;").unwrap();
+        assert_eq!(
+            json(&program),
+            serde_json::json!([{"type": "Let", "data": {
+                "ident": {"name": "x", "location": {"start": 4, "end": 5, "line": 1, "column": 5}},
+                "value": {"type": "Infix", "data": {
+                    "left": {"type": "Call", "data": {
+                        "function": {"type": "Ident", "data": {
+                            "name": "f",
+                            "location": {"start": 8, "end": 9, "line": 1, "column": 9}}},
+                        "arguments": [{"type": "Const", "data": 1}]}},
+                    "operator": {"type": "Asterisk"},
+                    "right": {"type": "Const", "data": 2},
+                    "location": {"start": 13, "end": 14, "line": 1, "column": 14}}}}}])
+        );
+    }
+}
@@ -0,0 +1,394 @@
+//! Embedding API.
+//!
+//! An `Engine` owns an evaluator whose globals persist between calls, lets the host expose Rust
+//! closures to scripts with `register_fn` and call script functions with `call_fn`. Arguments and
+//! results cross the boundary through `FromValue` and `IntoValue`.
+//!
+//! ```
+//! use amp::engine::Engine;
+//!
+//! let mut engine = Engine::new();
+//! engine.register_fn("greet", |name: String, times: i64| -> Result<String, String> {
+//!     if times < 0 {
+//!         return Err("negative count".to_string());
+//!     }
+//!     Ok(format!("hello {}", name).repeat(times as usize))
+//! });
+//! engine.eval::<()>("let twice = fn(name) { greet(name, 2) };").unwrap();
+//! let greeting: String = engine.call_fn("twice", ("amp",)).unwrap();
+//! assert_eq!(greeting, "hello amphello amp");
+//! ```
+use super::{
+    builtins::check_arity,
+    eval::Evaluator,
+    value::{Builtin, HashKey, Value},
+    AmpError,
+};
+use std::{collections::BTreeMap, fmt, rc::Rc};
+
+/// Conversion from a script value.
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> Result<Self, AmpError>;
+}
+
+/// Conversion into a script value.
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+fn mismatch(expected: &'static str, value: &Value) -> AmpError {
+    AmpError::TypeMismatch {
+        expected,
+        got: value.type_name(),
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Result<Value, AmpError> {
+        Ok(value)
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: Value) -> Result<i64, AmpError> {
+        match value {
+            Value::Integer(n) => Ok(n),
+            other => Err(mismatch("integer", &other)),
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> Result<bool, AmpError> {
+        match value {
+            Value::Boolean(b) => Ok(b),
+            other => Err(mismatch("boolean", &other)),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> Result<String, AmpError> {
+        match value {
+            Value::String(s) => Ok(s),
+            other => Err(mismatch("string", &other)),
+        }
+    }
+}
+
+impl FromValue for () {
+    fn from_value(value: Value) -> Result<(), AmpError> {
+        match value {
+            Value::Null => Ok(()),
+            other => Err(mismatch("null", &other)),
+        }
+    }
+}
+
+/// `null` converts to `None`.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> Result<Option<T>, AmpError> {
+        match value {
+            Value::Null => Ok(None),
+            other => T::from_value(other).map(Some),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value) -> Result<Vec<T>, AmpError> {
+        match value {
+            Value::Array(values) => values.iter().cloned().map(T::from_value).collect(),
+            other => Err(mismatch("array", &other)),
+        }
+    }
+}
+
+/// Only hashes with string keys convert.
+impl<T: FromValue> FromValue for BTreeMap<String, T> {
+    fn from_value(value: Value) -> Result<BTreeMap<String, T>, AmpError> {
+        let entries = match value {
+            Value::Hash(entries) => entries,
+            other => return Err(mismatch("hash", &other)),
+        };
+        entries
+            .iter()
+            .map(|(key, value)| match key {
+                HashKey::String(key) => Ok((key.clone(), T::from_value(value.clone())?)),
+                key => Err(mismatch("string", &Value::from(key.clone()))),
+            })
+            .collect()
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> Value {
+        Value::Integer(self)
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Boolean(self)
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::String(self)
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::String(self.to_string())
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Null
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        self.map_or(Value::Null, T::into_value)
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        Value::Array(Rc::new(self.into_iter().map(T::into_value).collect()))
+    }
+}
+
+impl<T: IntoValue> IntoValue for BTreeMap<String, T> {
+    fn into_value(self) -> Value {
+        let entries = self
+            .into_iter()
+            .map(|(key, value)| (HashKey::String(key), value.into_value()))
+            .collect();
+        Value::Hash(Rc::new(entries))
+    }
+}
+
+/// Result of a registered function. Plain values are returned as they are, the error of a
+/// `Result` becomes `AmpError::Host`.
+pub trait IntoResult {
+    fn into_result(self, name: &str) -> Result<Value, AmpError>;
+}
+
+impl<T: IntoValue> IntoResult for T {
+    fn into_result(self, _: &str) -> Result<Value, AmpError> {
+        Ok(self.into_value())
+    }
+}
+
+impl<T: IntoValue, E: fmt::Display> IntoResult for Result<T, E> {
+    fn into_result(self, name: &str) -> Result<Value, AmpError> {
+        self.map(T::into_value).map_err(|e| AmpError::Host {
+            name: name.to_string(),
+            message: e.to_string(),
+        })
+    }
+}
+
+/// A Rust closure that can be registered as a script function. Implemented for closures of up to
+/// five `FromValue` arguments returning an `IntoResult`.
+pub trait NativeFn<Args> {
+    fn into_builtin(self, name: &str) -> Builtin;
+}
+
+/// Arguments of `Engine::call_fn`, implemented for tuples of up to five `IntoValue`s.
+pub trait IntoArgs {
+    fn into_args(self) -> Vec<Value>;
+}
+
+impl IntoArgs for Vec<Value> {
+    fn into_args(self) -> Vec<Value> {
+        self
+    }
+}
+
+macro_rules! impl_native_fn {
+    ($count:expr; $($arg:ident),*) => {
+        impl<Func, Ret, $($arg),*> NativeFn<($($arg,)*)> for Func
+        where
+            Func: Fn($($arg),*) -> Ret + 'static,
+            Ret: IntoResult,
+            $($arg: FromValue,)*
+        {
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn into_builtin(self, name: &str) -> Builtin {
+                let owned_name = name.to_string();
+                Builtin::new(name, move |_, arguments| {
+                    let name = owned_name.as_str();
+                    check_arity(name, arguments, $count..=$count)?;
+                    let mut arguments = arguments.iter().cloned().enumerate();
+                    $(
+                        let (position, value) = arguments.next().unwrap();
+                        let $arg = $arg::from_value(value).map_err(|e| AmpError::InvalidArgument {
+                            name: name.to_string(),
+                            position: position + 1,
+                            message: e.to_string(),
+                        })?;
+                    )*
+                    self($($arg),*).into_result(name)
+                })
+            }
+        }
+
+        impl<$($arg: IntoValue),*> IntoArgs for ($($arg,)*) {
+            #[allow(non_snake_case)]
+            fn into_args(self) -> Vec<Value> {
+                let ($($arg,)*) = self;
+                vec![$($arg.into_value()),*]
+            }
+        }
+    };
+}
+
+impl_native_fn!(0;);
+impl_native_fn!(1; A);
+impl_native_fn!(2; A, B);
+impl_native_fn!(3; A, B, C);
+impl_native_fn!(4; A, B, C, D);
+impl_native_fn!(5; A, B, C, D, E);
+
+#[derive(Default)]
+pub struct Engine {
+    evaluator: Evaluator,
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        Engine::default()
+    }
+
+    /// Creates an engine around an existing evaluator, e.g. one with a custom output.
+    pub fn with_evaluator(evaluator: Evaluator) -> Engine {
+        Engine { evaluator }
+    }
+
+    /// Makes `function` callable from scripts as the global `name`.
+    pub fn register_fn<Args, F: NativeFn<Args>>(&mut self, name: &str, function: F) -> &mut Engine {
+        let builtin = function.into_builtin(name);
+        self.evaluator.define(name, Value::Builtin(Rc::new(builtin)));
+        self
+    }
+
+    /// Defines or replaces the global `name`.
+    pub fn set<T: IntoValue>(&mut self, name: &str, value: T) {
+        self.evaluator.define(name, value.into_value());
+    }
+
+    pub fn get<T: FromValue>(&self, name: &str) -> Result<T, AmpError> {
+        match self.evaluator.global(name) {
+            Some(value) => T::from_value(value.clone()),
+            None => Err(AmpError::UndefinedVariable(name.to_string())),
+        }
+    }
+
+    pub fn eval<T: FromValue>(&mut self, source: &str) -> Result<T, AmpError> {
+        T::from_value(self.evaluator.eval(source)?)
+    }
+
+    /// Calls the script function stored in the global `name`.
+    pub fn call_fn<T: FromValue, A: IntoArgs>(&mut self, name: &str, arguments: A) -> Result<T, AmpError> {
+        let function = match self.evaluator.global(name) {
+            Some(function) => function.clone(),
+            None => return Err(AmpError::UndefinedVariable(name.to_string())),
+        };
+        T::from_value(self.evaluator.call(function, arguments.into_args())?)
+    }
+
+    pub fn evaluator(&mut self) -> &mut Evaluator {
+        &mut self.evaluator
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calls_host_functions() {
+        let mut engine = Engine::new();
+        engine
+            .register_fn("add", |a: i64, b: i64| a + b)
+            .register_fn("answer", || 42)
+            .register_fn("join", |parts: Vec<String>, separator: Option<String>| {
+                parts.join(&separator.unwrap_or_default())
+            })
+            .register_fn("checked_div", |a: i64, b: i64| -> Result<i64, String> {
+                a.checked_div(b).ok_or_else(|| "division by zero".to_string())
+            });
+
+        assert_eq!(engine.eval::<i64>("add(answer(), 8)"), Ok(50));
+        assert_eq!(
+            engine.eval::<String>(r#"join(["a", "b"], "-") + join(["c"], {}["separator"])"#),
+            Ok("a-bc".to_string())
+        );
+        assert_eq!(
+            engine.eval::<i64>("checked_div(1, 0)").unwrap_err().to_string(),
+            "`checked_div` failed: division by zero"
+        );
+        assert_eq!(
+            engine.eval::<i64>(r#"add(1, "2")"#).unwrap_err().to_string(),
+            "invalid argument 2 to `add`: expected integer, got string"
+        );
+        assert_eq!(
+            engine.eval::<i64>("add(1)").unwrap_err().to_string(),
+            "`add` expects 2 arguments, got 1"
+        );
+    }
+
+    #[test]
+    fn calls_script_functions() {
+        let mut engine = Engine::new();
+        engine
+            .eval::<Value>("let scale = fn(values, factor) { [values[0] * factor, values[1] * factor] };")
+            .unwrap();
+        assert_eq!(engine.call_fn::<Vec<i64>, _>("scale", (vec![1, 2], 3)), Ok(vec![3, 6]));
+        assert_eq!(
+            engine.call_fn::<i64, _>("scale", (1,)),
+            Err(AmpError::WrongArgumentCount { expected: 2, got: 1 })
+        );
+        assert_eq!(
+            engine.call_fn::<i64, _>("missing", ()),
+            Err(AmpError::UndefinedVariable("missing".to_string()))
+        );
+    }
+
+    #[test]
+    fn converts_globals() {
+        let mut engine = Engine::new();
+        let mut config = BTreeMap::new();
+        config.insert("limit".to_string(), 10);
+        engine.set("config", config);
+        engine.eval::<()>(r#"let limit = config["limit"] + 1;"#).unwrap();
+
+        assert_eq!(engine.get::<i64>("limit"), Ok(11));
+        assert_eq!(
+            engine.get::<bool>("limit"),
+            Err(AmpError::TypeMismatch {
+                expected: "boolean",
+                got: "integer"
+            })
+        );
+        assert_eq!(
+            engine
+                .eval::<BTreeMap<String, i64>>(r#"{"a": 1, "b": 2}"#)
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+}
@@ -0,0 +1,1080 @@
+//! Tree-walking evaluator.
+//!
+//! Programs are resolved before they run, so locals are read by the scope depth and slot assigned
+//! by the resolver while globals live in a map owned by the `Evaluator` and survive between
+//! calls to `Evaluator::eval`. Every run is checked against the evaluator's `Limits`.
+use super::{
+    ast::{Expr, Ident, Statement, Token},
+    builtins,
+    limits::{Limits, Usage},
+    parser::parse_program,
+    resolver::Resolver,
+    value::{Function, HashKey, Value},
+    AmpError,
+};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    io::{self, Write},
+    rc::Rc,
+    time::Duration,
+};
+
+pub(crate) type Env = Rc<RefCell<Scope>>;
+
+/// A local scope. Values are stored in the order of their slots.
+#[derive(Default)]
+pub(crate) struct Scope {
+    values: Vec<Value>,
+    parent: Option<Env>,
+}
+
+impl Scope {
+    fn child(parent: &Option<Env>, values: Vec<Value>) -> Env {
+        Rc::new(RefCell::new(Scope {
+            values,
+            parent: parent.clone(),
+        }))
+    }
+}
+
+/// Non-local exit from an expression.
+enum Unwind {
+    Return(Value),
+    Break,
+    Continue,
+    Error(AmpError),
+}
+
+impl Unwind {
+    /// Ends the unwinding at a function or program boundary. The resolver rejects `break` and
+    /// `continue` outside of loops, so they only get here in unresolved programs.
+    fn finish(result: Eval<Value>) -> Result<Value, AmpError> {
+        match result {
+            Ok(value) | Err(Unwind::Return(value)) => Ok(value),
+            Err(Unwind::Break) => Err(AmpError::OutsideLoop("break")),
+            Err(Unwind::Continue) => Err(AmpError::OutsideLoop("continue")),
+            Err(Unwind::Error(e)) => Err(e),
+        }
+    }
+}
+
+impl From<AmpError> for Unwind {
+    fn from(e: AmpError) -> Unwind {
+        Unwind::Error(e)
+    }
+}
+
+type Eval<T> = Result<T, Unwind>;
+
+pub struct Evaluator {
+    globals: HashMap<String, Value>,
+    builtins: HashMap<&'static str, Value>,
+    output: Box<dyn Write>,
+    limits: Limits,
+    usage: Usage,
+    deadline: Option<Duration>,
+    running: bool,
+}
+
+impl Default for Evaluator {
+    fn default() -> Evaluator {
+        Evaluator::with_output(Box::new(io::stdout()))
+    }
+}
+
+impl Evaluator {
+    pub fn new() -> Evaluator {
+        Evaluator::default()
+    }
+
+    /// Creates an evaluator whose `puts` and `print` write to `output` instead of stdout.
+    pub fn with_output(output: Box<dyn Write>) -> Evaluator {
+        Evaluator {
+            globals: HashMap::new(),
+            builtins: builtins::all().into_iter().collect(),
+            output,
+            limits: Limits::default(),
+            usage: Usage::default(),
+            deadline: None,
+            running: false,
+        }
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Resources used by the current or the last run.
+    pub fn usage(&self) -> Usage {
+        self.usage
+    }
+
+    pub fn output(&mut self) -> &mut dyn Write {
+        self.output.as_mut()
+    }
+
+    /// Returns an evaluator with a copy of the globals and the limits of this one that writes to
+    /// `output`. Scopes captured by closures are copied as well, so nothing the copy runs can
+    /// change this evaluator.
+    pub(crate) fn fork(&self, output: Box<dyn Write>) -> Evaluator {
+        let mut fork = Evaluator::with_output(output);
+        fork.limits = self.limits.clone();
+        let mut copies = HashMap::new();
+        for (name, value) in &self.globals {
+            fork.globals.insert(name.clone(), copy_value(value, &mut copies));
+        }
+        fork
+    }
+
+    /// Parses, resolves and runs `source`. Returns the value of the last statement or of the
+    /// first top level `return`.
+    pub fn eval(&mut self, source: &str) -> Result<Value, AmpError> {
+        let mut program = parse_program(source)?;
+        let mut resolver = Resolver::new();
+        for name in self.globals.keys() {
+            resolver.declare(name);
+        }
+        let resolution = resolver.resolve(&mut program);
+        if resolution.has_errors() {
+            return Err(AmpError::Resolution(resolution.diagnostics));
+        }
+        self.eval_program(&program)
+    }
+
+    /// Runs an already resolved program.
+    pub fn eval_program(&mut self, program: &[Statement]) -> Result<Value, AmpError> {
+        self.run(|evaluator| Unwind::finish(evaluator.eval_block(program, &None)))
+    }
+
+    /// Runs `f` with a fresh budget unless a run is already in progress.
+    fn run<T>(&mut self, f: impl FnOnce(&mut Evaluator) -> T) -> T {
+        if self.running {
+            return f(self);
+        }
+        self.usage = Usage::default();
+        self.deadline = self.limits.timeout.map(|timeout| self.limits.clock.now() + timeout);
+        self.running = true;
+        let result = f(self);
+        self.running = false;
+        result
+    }
+
+    /// Uses one unit of fuel and checks the deadline.
+    fn step(&mut self) -> Result<(), AmpError> {
+        self.usage.steps += 1;
+        if let Some(fuel) = self.limits.fuel {
+            if self.usage.steps > fuel {
+                return Err(AmpError::OutOfFuel(fuel));
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if self.limits.clock.now() >= deadline {
+                return Err(AmpError::DeadlineExceeded(self.limits.timeout.unwrap_or_default()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Accounts for `amount` string bytes or array and hash elements about to be created.
+    pub(crate) fn allocate(&mut self, amount: usize) -> Result<(), AmpError> {
+        self.usage.memory = self.usage.memory.saturating_add(amount);
+        match self.limits.max_memory {
+            Some(max) if self.usage.memory > max => Err(AmpError::MemoryLimitExceeded(max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Defines or replaces a global binding.
+    pub fn define(&mut self, name: &str, value: Value) {
+        self.globals.insert(name.to_string(), value);
+    }
+
+    pub fn global(&self, name: &str) -> Option<&Value> {
+        self.globals.get(name)
+    }
+
+    /// Global bindings sorted by name, without builtins.
+    pub fn globals(&self) -> Vec<(&String, &Value)> {
+        let mut globals: Vec<_> = self.globals.iter().collect();
+        globals.sort_by(|a, b| a.0.cmp(b.0));
+        globals
+    }
+
+    fn eval_block(&mut self, statements: &[Statement], env: &Option<Env>) -> Eval<Value> {
+        let mut last = Value::Null;
+        for statement in statements {
+            last = self.eval_statement(statement, env)?;
+        }
+        Ok(last)
+    }
+
+    fn eval_statement(&mut self, statement: &Statement, env: &Option<Env>) -> Eval<Value> {
+        self.step()?;
+        match statement {
+            Statement::Let { ident, value } => {
+                let value = self.eval_expr(value, env)?;
+                match env {
+                    Some(env) => env.borrow_mut().values.push(value),
+                    None => {
+                        self.globals.insert(ident.to_string(), value);
+                    }
+                }
+                Ok(Value::Null)
+            }
+            Statement::Expression(expr) => self.eval_expr(expr, env),
+            Statement::Return { value } => Err(Unwind::Return(self.eval_expr(value, env)?)),
+            Statement::While { condition, body } => {
+                while self.eval_expr(condition, env)?.is_truthy() {
+                    match self.eval_block(body, &Some(Scope::child(env, Vec::new()))) {
+                        Ok(_) | Err(Unwind::Continue) => {}
+                        Err(Unwind::Break) => break,
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(Value::Null)
+            }
+            Statement::For { iterable, body, .. } => {
+                for item in self.eval_expr(iterable, env)?.iter()? {
+                    if let Value::String(s) = &item {
+                        self.allocate(s.len())?;
+                    }
+                    match self.eval_block(body, &Some(Scope::child(env, vec![item]))) {
+                        Ok(_) | Err(Unwind::Continue) => {}
+                        Err(Unwind::Break) => break,
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(Value::Null)
+            }
+            Statement::Break(_) => Err(Unwind::Break),
+            Statement::Continue(_) => Err(Unwind::Continue),
+            Statement::Empty => Ok(Value::Null),
+        }
+    }
+
+    fn eval_expr(&mut self, expr: &Expr, env: &Option<Env>) -> Eval<Value> {
+        self.step()?;
+        match expr {
+            Expr::Const(n) => Ok(Value::Integer(
+                i64::try_from(*n).map_err(|_| AmpError::IntegerOverflow)?,
+            )),
+            Expr::String(s) => {
+                self.allocate(s.len())?;
+                Ok(Value::String(s.clone()))
+            }
+            Expr::Boolean(b) => Ok(Value::Boolean(*b)),
+            Expr::Ident(Ident { name, .. }) => {
+                match self.globals.get(name).or_else(|| self.builtins.get(name.as_str())) {
+                    Some(value) => Ok(value.clone()),
+                    None => Err(AmpError::UndefinedVariable(name.clone()).into()),
+                }
+            }
+            Expr::Local {
+                name: Ident { name, .. },
+                depth,
+                slot,
+            } => lookup(env, *depth, *slot).ok_or_else(|| AmpError::UndefinedVariable(name.clone()).into()),
+            Expr::Prefix { prefix, value } => {
+                let value = self.eval_expr(value, env)?;
+                Ok(eval_prefix(prefix, value)?)
+            }
+            Expr::Infix {
+                left,
+                operator: operator @ (Token::And | Token::Or),
+                right,
+                ..
+            } => {
+                // `&&` only evaluates its right operand if the left one is truthy, `||` only if
+                // it is falsy. Both evaluate to a boolean.
+                let left = self.eval_expr(left, env)?.is_truthy();
+                if left == (*operator == Token::Or) {
+                    return Ok(Value::Boolean(left));
+                }
+                Ok(Value::Boolean(self.eval_expr(right, env)?.is_truthy()))
+            }
+            Expr::Infix {
+                left, operator, right, ..
+            } => {
+                let left = self.eval_expr(left, env)?;
+                let right = self.eval_expr(right, env)?;
+                Ok(self.eval_binary(operator, left, right)?)
+            }
+            Expr::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => {
+                let branch = if self.eval_expr(condition, env)?.is_truthy() {
+                    consequence
+                } else {
+                    alternative
+                };
+                self.eval_block(branch, &Some(Scope::child(env, Vec::new())))
+            }
+            Expr::Block(body) => self.eval_block(body, &Some(Scope::child(env, Vec::new()))),
+            Expr::Function { parameters, body } => Ok(Value::Function(Rc::new(Function {
+                parameters: parameters.iter().map(|parameter| parameter.name.clone()).collect(),
+                body: body.clone(),
+                env: env.clone(),
+            }))),
+            Expr::Call { function, arguments } => {
+                let function = self.eval_expr(function, env)?;
+                let arguments = self.eval_exprs(arguments, env)?;
+                Ok(self.call_value(function, arguments)?)
+            }
+            Expr::Array(elements) => {
+                self.allocate(elements.len())?;
+                Ok(Value::Array(Rc::new(self.eval_exprs(elements, env)?)))
+            }
+            Expr::Hash(pairs) => self.eval_hash(pairs, env),
+            Expr::Index { left, index } => {
+                let left = self.eval_expr(left, env)?;
+                let index = self.eval_expr(index, env)?;
+                Ok(eval_index(left, index)?)
+            }
+            Expr::Assign {
+                target,
+                operator,
+                value,
+            } => self.eval_assign(target, operator, value, env),
+            Expr::Unknown => Ok(Value::Null),
+        }
+    }
+
+    fn eval_binary(&mut self, operator: &Token, left: Value, right: Value) -> Result<Value, AmpError> {
+        if let (Token::Plus, Value::String(a), Value::String(b)) = (operator, &left, &right) {
+            self.allocate(a.len() + b.len())?;
+        }
+        eval_infix(operator, left, right)
+    }
+
+    /// Assigns to a variable or to an element of an array or hash stored in one. Arrays and hashes
+    /// are values: an element assignment copies a container that is shared with another variable
+    /// and stores the new container in the assigned variable. Indices are evaluated once, in
+    /// source order, and the whole path is checked before anything changes. Evaluates to the
+    /// assigned value.
+    fn eval_assign(&mut self, target: &Expr, operator: &Token, value: &Expr, env: &Option<Env>) -> Eval<Value> {
+        let mut indices = Vec::new();
+        let mut variable = target;
+        while let Expr::Index { left, index } = variable {
+            indices.push(index);
+            variable = left;
+        }
+        let mut keys = Vec::with_capacity(indices.len());
+        for index in indices.into_iter().rev() {
+            keys.push(self.eval_expr(index, env)?);
+        }
+        let mut value = self.eval_expr(value, env)?;
+
+        let mut current = self.eval_expr(variable, env)?;
+        let mut new_key = false;
+        for key in &keys {
+            new_key = match &current {
+                Value::Hash(entries) => !entries.contains_key(&HashKey::from_value(key)?),
+                _ => false,
+            };
+            current = eval_index(current, key.clone())?;
+        }
+        if let Some(operator) = operator.compound_operator() {
+            value = self.eval_binary(&operator, current, value)?;
+        } else {
+            drop(current);
+        }
+        if new_key {
+            self.allocate(1)?;
+        }
+
+        // Taking the container out of the variable leaves it unshared, so it is updated in place.
+        let container = self.replace_variable(variable, Value::Null, env)?;
+        self.replace_variable(variable, set_element(container, &keys, value.clone()), env)?;
+        Ok(value)
+    }
+
+    /// Stores `value` in a variable and returns its previous value.
+    fn replace_variable(&mut self, variable: &Expr, value: Value, env: &Option<Env>) -> Result<Value, AmpError> {
+        match variable {
+            Expr::Local {
+                name: Ident { name, .. },
+                depth,
+                slot,
+            } => replace_local(env, *depth, *slot, value).ok_or_else(|| AmpError::UndefinedVariable(name.clone())),
+            Expr::Ident(Ident { name, .. }) => match self.globals.get_mut(name) {
+                Some(global) => Ok(std::mem::replace(global, value)),
+                None => Err(AmpError::UndefinedVariable(name.clone())),
+            },
+            _ => Err(AmpError::InvalidAssignmentTarget),
+        }
+    }
+
+    fn eval_exprs(&mut self, exprs: &[Expr], env: &Option<Env>) -> Eval<Vec<Value>> {
+        let mut values = Vec::with_capacity(exprs.len());
+        for expr in exprs {
+            values.push(self.eval_expr(expr, env)?);
+        }
+        Ok(values)
+    }
+
+    fn eval_hash(&mut self, pairs: &[(Expr, Expr)], env: &Option<Env>) -> Eval<Value> {
+        self.allocate(pairs.len())?;
+        let mut entries = BTreeMap::new();
+        for (key, value) in pairs {
+            let key = HashKey::from_value(&self.eval_expr(key, env)?)?;
+            entries.insert(key, self.eval_expr(value, env)?);
+        }
+        Ok(Value::Hash(Rc::new(entries)))
+    }
+
+    /// Calls a function or a builtin, starting a new run unless one is in progress.
+    pub(crate) fn call(&mut self, function: Value, arguments: Vec<Value>) -> Result<Value, AmpError> {
+        self.run(|evaluator| evaluator.call_value(function, arguments))
+    }
+
+    fn call_value(&mut self, function: Value, arguments: Vec<Value>) -> Result<Value, AmpError> {
+        let function = match function {
+            Value::Function(function) => function,
+            Value::Builtin(builtin) => return (builtin.function)(self, &arguments),
+            other => return Err(AmpError::NotCallable(other.type_name())),
+        };
+        if function.parameters.len() != arguments.len() {
+            return Err(AmpError::WrongArgumentCount {
+                expected: function.parameters.len(),
+                got: arguments.len(),
+            });
+        }
+
+        if let Some(max) = self.limits.max_call_depth {
+            if self.usage.call_depth >= max {
+                return Err(AmpError::CallDepthExceeded(max));
+            }
+        }
+
+        let env = Some(Scope::child(&function.env, arguments));
+        self.usage.call_depth += 1;
+        let result = self.eval_block(&function.body, &env);
+        self.usage.call_depth -= 1;
+        Unwind::finish(result)
+    }
+}
+
+/// Copies `value` with fresh scopes for the closures in it. `copies` maps the scopes copied so far
+/// to their copies, so scopes shared by several closures, or by a closure and itself, stay shared.
+fn copy_value(value: &Value, copies: &mut HashMap<*const RefCell<Scope>, Env>) -> Value {
+    match value {
+        Value::Function(function) => match &function.env {
+            Some(env) => Value::Function(Rc::new(Function {
+                parameters: function.parameters.clone(),
+                body: function.body.clone(),
+                env: Some(copy_scope(env, copies)),
+            })),
+            None => value.clone(),
+        },
+        Value::Array(elements) => Value::Array(Rc::new(elements.iter().map(|v| copy_value(v, copies)).collect())),
+        Value::Hash(entries) => Value::Hash(Rc::new(
+            entries
+                .iter()
+                .map(|(key, value)| (key.clone(), copy_value(value, copies)))
+                .collect(),
+        )),
+        _ => value.clone(),
+    }
+}
+
+fn copy_scope(env: &Env, copies: &mut HashMap<*const RefCell<Scope>, Env>) -> Env {
+    if let Some(copy) = copies.get(&Rc::as_ptr(env)) {
+        return copy.clone();
+    }
+    // Registered before the values are copied, they may contain closures over this scope.
+    let copy = Rc::new(RefCell::new(Scope::default()));
+    copies.insert(Rc::as_ptr(env), copy.clone());
+    let scope = env.borrow();
+    let parent = scope.parent.as_ref().map(|parent| copy_scope(parent, copies));
+    let values = scope.values.iter().map(|value| copy_value(value, copies)).collect();
+    *copy.borrow_mut() = Scope { values, parent };
+    copy
+}
+
+fn lookup(env: &Option<Env>, depth: usize, slot: usize) -> Option<Value> {
+    let mut scope = env.clone()?;
+    for _ in 0..depth {
+        let parent = scope.borrow().parent.clone()?;
+        scope = parent;
+    }
+    let value = scope.borrow().values.get(slot).cloned();
+    value
+}
+
+/// Replaces the value in `slot` of the scope `depth` levels up and returns the previous one.
+fn replace_local(env: &Option<Env>, depth: usize, slot: usize, value: Value) -> Option<Value> {
+    let mut scope = env.clone()?;
+    for _ in 0..depth {
+        let parent = scope.borrow().parent.clone()?;
+        scope = parent;
+    }
+    let mut scope = scope.borrow_mut();
+    let current = scope.values.get_mut(slot)?;
+    Some(std::mem::replace(current, value))
+}
+
+/// Returns `container` with the element at the path of `keys` replaced by `value`. The path must
+/// have been checked with `eval_index`, elements it can't reach are left unchanged.
+fn set_element(container: Value, keys: &[Value], value: Value) -> Value {
+    let (key, rest) = match keys.split_first() {
+        Some(split) => split,
+        None => return value,
+    };
+    match (container, key) {
+        (Value::Array(mut values), Value::Integer(i)) => {
+            let len = values.len() as i64;
+            let position = if *i < 0 { i + len } else { *i };
+            if let Some(element) = usize::try_from(position)
+                .ok()
+                .and_then(|position| Rc::make_mut(&mut values).get_mut(position))
+            {
+                let current = std::mem::replace(element, Value::Null);
+                *element = set_element(current, rest, value);
+            }
+            Value::Array(values)
+        }
+        (Value::Hash(mut entries), key) => {
+            if let Ok(key) = HashKey::from_value(key) {
+                let entries_mut = Rc::make_mut(&mut entries);
+                let current = entries_mut.remove(&key).unwrap_or(Value::Null);
+                entries_mut.insert(key, set_element(current, rest, value));
+            }
+            Value::Hash(entries)
+        }
+        (container, _) => container,
+    }
+}
+
+/// Indexes an array or a hash. Negative array indices count from the end, so `-1` is the last
+/// element. A missing hash key yields `null`.
+fn eval_index(left: Value, index: Value) -> Result<Value, AmpError> {
+    match (&left, &index) {
+        (Value::Array(values), Value::Integer(i)) => {
+            let len = values.len() as i64;
+            let position = if *i < 0 { i + len } else { *i };
+            if position < 0 || position >= len {
+                return Err(AmpError::IndexOutOfBounds {
+                    index: *i,
+                    len: values.len(),
+                });
+            }
+            Ok(values[position as usize].clone())
+        }
+        (Value::Array(_), _) => Err(AmpError::UnsupportedOperand(format!("array[{}]", index.type_name()))),
+        (Value::Hash(entries), _) => Ok(entries
+            .get(&HashKey::from_value(&index)?)
+            .cloned()
+            .unwrap_or(Value::Null)),
+        _ => Err(AmpError::NotIndexable(left.type_name())),
+    }
+}
+
+fn eval_prefix(prefix: &Token, value: Value) -> Result<Value, AmpError> {
+    match (prefix, value) {
+        (Token::Bang, value) => Ok(Value::Boolean(!value.is_truthy())),
+        (Token::Minus, Value::Integer(n)) => n.checked_neg().map(Value::Integer).ok_or(AmpError::IntegerOverflow),
+        (Token::Tilde, Value::Integer(n)) => Ok(Value::Integer(!n)),
+        (prefix, value) => Err(AmpError::UnsupportedOperand(format!("{}{}", prefix, value.type_name()))),
+    }
+}
+
+fn eval_infix(operator: &Token, left: Value, right: Value) -> Result<Value, AmpError> {
+    match (operator, &left, &right) {
+        (Token::Equal, _, _) => Ok(Value::Boolean(left == right)),
+        (Token::NotEqual, _, _) => Ok(Value::Boolean(left != right)),
+        (_, Value::Integer(a), Value::Integer(b)) => eval_integer_infix(operator, *a, *b),
+        (Token::Plus, Value::String(a), Value::String(b)) => Ok(Value::String(format!("{}{}", a, b))),
+        _ => Err(AmpError::UnsupportedOperand(format!(
+            "{} {} {}",
+            left.type_name(),
+            operator,
+            right.type_name()
+        ))),
+    }
+}
+
+/// `/` and `%` round toward zero, so `-7 % 3` is `-1`. `**` rejects negative exponents and both
+/// shifts amounts outside of `0..64`; `>>` keeps the sign and `<<` drops the bits shifted out.
+fn eval_integer_infix(operator: &Token, a: i64, b: i64) -> Result<Value, AmpError> {
+    let arithmetic = |result: Option<i64>| result.map(Value::Integer).ok_or(AmpError::IntegerOverflow);
+    match operator {
+        Token::Plus => arithmetic(a.checked_add(b)),
+        Token::Minus => arithmetic(a.checked_sub(b)),
+        Token::Asterisk => arithmetic(a.checked_mul(b)),
+        Token::Slash if b == 0 => Err(AmpError::DivisionByZero),
+        Token::Slash => arithmetic(a.checked_div(b)),
+        Token::Percent if b == 0 => Err(AmpError::DivisionByZero),
+        Token::Percent => arithmetic(a.checked_rem(b)),
+        Token::Power if b < 0 => Err(AmpError::NegativeExponent(b)),
+        Token::Power => arithmetic(u32::try_from(b).ok().and_then(|b| a.checked_pow(b))),
+        Token::Ampersand => Ok(Value::Integer(a & b)),
+        Token::Pipe => Ok(Value::Integer(a | b)),
+        Token::Caret => Ok(Value::Integer(a ^ b)),
+        Token::ShiftLeft | Token::ShiftRight if !(0..64).contains(&b) => Err(AmpError::InvalidShift(b)),
+        Token::ShiftLeft => Ok(Value::Integer(a << b)),
+        Token::ShiftRight => Ok(Value::Integer(a >> b)),
+        Token::LessThan => Ok(Value::Boolean(a < b)),
+        Token::GreaterThan => Ok(Value::Boolean(a > b)),
+        Token::LessThanOrEqual => Ok(Value::Boolean(a <= b)),
+        Token::GreaterThanOrEqual => Ok(Value::Boolean(a >= b)),
+        Token::DotDot | Token::DotDotEqual => Ok(Value::Range {
+            start: a,
+            end: b,
+            inclusive: *operator == Token::DotDotEqual,
+        }),
+        _ => Err(AmpError::UnsupportedOperand(format!("integer {} integer", operator))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::{Clock, DEFAULT_MAX_CALL_DEPTH};
+    use std::cell::Cell;
+
+    fn eval(source: &str) -> Result<Value, AmpError> {
+        Evaluator::new().eval(source)
+    }
+
+    #[test]
+    fn evaluates_arithmetic_and_comparisons() {
+        assert_eq!(eval("1 + 2 * 3 - 4 / 2"), Ok(Value::Integer(5)));
+        assert_eq!(eval("-(1 + 2)"), Ok(Value::Integer(-3)));
+        assert_eq!(eval("1 < 2 == true"), Ok(Value::Boolean(true)));
+        assert_eq!(eval("!5"), Ok(Value::Boolean(false)));
+        assert_eq!(eval("1 == true"), Ok(Value::Boolean(false)));
+        assert_eq!(eval("1 / 0"), Err(AmpError::DivisionByZero));
+        assert_eq!(eval("9223372036854775807 + 1"), Err(AmpError::IntegerOverflow));
+        assert_eq!(
+            eval("1 + true"),
+            Err(AmpError::UnsupportedOperand("integer + boolean".to_string()))
+        );
+    }
+
+    #[test]
+    fn evaluates_modulo_power_and_bitwise_operators() {
+        let results = [
+            ("17 % 5", 2),
+            ("-7 % 3", -1),
+            ("7 % -3", 1),
+            ("2 ** 10", 1024),
+            ("2 ** 3 ** 2", 512),
+            ("-2 ** 2", -4),
+            ("(-2) ** 3", -8),
+            ("5 ** 0", 1),
+            ("2 * 3 ** 2 % 5", 3),
+            ("12 & 10", 8),
+            ("12 | 10", 14),
+            ("12 ^ 10", 6),
+            ("~5", -6),
+            ("1 << 4 + 1", 32),
+            ("-16 >> 2", -4),
+            ("1 << 63", i64::MIN),
+            ("1 | 2 ^ 3 & 4", 3),
+        ];
+        for (source, expected) in results.iter() {
+            assert_eq!(eval(source), Ok(Value::Integer(*expected)), "{}", source);
+        }
+        // Unlike in C, bitwise operators bind tighter than comparisons.
+        assert_eq!(eval("6 & 3 == 2"), Ok(Value::Boolean(true)));
+
+        assert_eq!(eval("1 % 0"), Err(AmpError::DivisionByZero));
+        assert_eq!(eval("(-9223372036854775807 - 1) % -1"), Err(AmpError::IntegerOverflow));
+        assert_eq!(eval("2 ** -1"), Err(AmpError::NegativeExponent(-1)));
+        assert_eq!(eval("2 ** 63"), Err(AmpError::IntegerOverflow));
+        assert_eq!(eval("1 << 64"), Err(AmpError::InvalidShift(64)));
+        assert_eq!(eval("1 >> -1"), Err(AmpError::InvalidShift(-1)));
+        assert_eq!(
+            eval("true & false"),
+            Err(AmpError::UnsupportedOperand("boolean & boolean".to_string()))
+        );
+        assert_eq!(eval("~true"), Err(AmpError::UnsupportedOperand("~boolean".to_string())));
+    }
+
+    #[test]
+    fn evaluates_logical_operators() {
+        assert_eq!(eval("1 < 2 && 2 < 3 == true"), Ok(Value::Boolean(true)));
+        assert_eq!(eval("false || true && false"), Ok(Value::Boolean(false)));
+        assert_eq!(eval("!false && !!true"), Ok(Value::Boolean(true)));
+        assert_eq!(eval(r#"0 && "" && [] && {}"#), Ok(Value::Boolean(true)));
+        assert_eq!(eval(r#"{}["missing"] || false"#), Ok(Value::Boolean(false)));
+        assert_eq!(eval(r#"5 || "x""#), Ok(Value::Boolean(true)));
+
+        // The right operand is only evaluated when it decides the result.
+        assert_eq!(eval("false && 1 / 0"), Ok(Value::Boolean(false)));
+        assert_eq!(eval("true || 1 / 0"), Ok(Value::Boolean(true)));
+        assert_eq!(eval("true && 1 / 0"), Err(AmpError::DivisionByZero));
+        let source = "let calls = 0;
+let touch = fn(result) { calls += 1; result };
+touch(false) && touch(true);
+touch(true) || touch(true);
+touch(true) && touch(false) || touch(true);
+calls";
+        assert_eq!(eval(source), Ok(Value::Integer(5)));
+    }
+
+    #[test]
+    fn evaluates_functions_and_closures() {
+        let source = "let make_adder = fn(x) { fn(y) { x + y } };
+let add_two = make_adder(2);
+add_two(40)";
+        assert_eq!(eval(source), Ok(Value::Integer(42)));
+
+        let source = "let fact = fn(n) { if (n < 2) { return 1; } n * fact(n - 1) };
+fact(10)";
+        assert_eq!(eval(source), Ok(Value::Integer(3628800)));
+
+        let source = "let outer = fn() {
+    let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };
+    fib(15)
+};
+outer()";
+        assert_eq!(eval(source), Ok(Value::Integer(610)));
+    }
+
+    #[test]
+    fn evaluates_if_expressions() {
+        let source = "let sign = fn(n) {
+    if (n < 0) { -1 } else if (n == 0) { 0 } else { 1 }
+};
+let grade = fn(score) {
+    let letter = if (score >= 90) {
+        \"A\"
+    } else if (score >= 80) {
+        \"B\"
+    } else if (score >= 70) {
+        if (score >= 75) { \"C+\" } else { \"C\" }
+    } else {
+        \"F\"
+    };
+    letter
+};
+[sign(-5), sign(0), sign(7), grade(95), grade(85), grade(77), grade(71), grade(10)]";
+        assert_eq!(
+            eval(source).unwrap().to_string(),
+            r#"[-1, 0, 1, "A", "B", "C+", "C", "F"]"#
+        );
+        assert_eq!(eval("if (false) { 1 }"), Ok(Value::Null));
+        assert_eq!(
+            eval("let y = if (true) { 1 } else { 2 } + 10; y"),
+            Ok(Value::Integer(11))
+        );
+    }
+
+    #[test]
+    fn evaluates_block_expressions() {
+        assert_eq!(eval("let x = 1; { let x = 2; x * 10 } + x"), Ok(Value::Integer(21)));
+        assert_eq!(eval("{ let a = 3; let b = 4; a * b }"), Ok(Value::Integer(12)));
+        assert_eq!(eval("{ 1; }"), Ok(Value::Null));
+        assert_eq!(eval("let f = fn(x) { x * 2 }; f(21)"), Ok(Value::Integer(42)));
+        assert_eq!(eval("let f = fn(x) { x * 2; }; f(21)"), Ok(Value::Null));
+        assert_eq!(eval("if (true) { 1; }"), Ok(Value::Null));
+        // The value of a program is its last statement, with or without a semicolon.
+        assert_eq!(eval("1 + 2;"), Ok(Value::Integer(3)));
+        assert_eq!(
+            eval("let total = 0; for (i in 0..3) { total += { let sq = i * i; sq }; } total"),
+            Ok(Value::Integer(5))
+        );
+        assert!(matches!(eval("{ let y = 2; } y"), Err(AmpError::Resolution(_))));
+    }
+
+    #[test]
+    fn returns_from_nested_blocks() {
+        let source = "let f = fn(x) {
+    if (x > 0) {
+        if (x > 10) { return 2; }
+        return 1;
+    }
+    0
+};
+f(20) * 100 + f(5) * 10 + f(-1)";
+        assert_eq!(eval(source), Ok(Value::Integer(210)));
+        assert_eq!(eval("return 5; 6"), Ok(Value::Integer(5)));
+    }
+
+    #[test]
+    fn evaluates_while_loops() {
+        let source = "let find = fn(limit) {
+    while (true) {
+        let x = limit;
+        if (x > 2) { return x * 10; }
+        break;
+    }
+    x
+};
+let x = 1;
+[find(5), find(2)]";
+        assert_eq!(eval(source).unwrap().to_string(), "[50, 1]");
+        assert_eq!(eval("while (false) { 1 }"), Ok(Value::Null));
+
+        let mut evaluator = Evaluator::new();
+        evaluator.set_limits(Limits {
+            fuel: Some(100),
+            ..Limits::default()
+        });
+        assert_eq!(
+            evaluator.eval("while (true) { continue; 1 }"),
+            Err(AmpError::OutOfFuel(100))
+        );
+        assert_eq!(
+            evaluator.eval_program(&parse_program("break;").unwrap()),
+            Err(AmpError::OutsideLoop("break"))
+        );
+    }
+
+    #[test]
+    fn evaluates_for_loops() {
+        let source = r#"let find = fn(xs, skip) {
+    for (x in xs) {
+        if (x == skip) { continue; }
+        return x;
+    }
+    "none"
+};
+let nested = fn() {
+    for (x in [1]) {
+        let y = x;
+        for (x in [x * 10]) { return x + y; }
+    }
+};
+let stop = fn() {
+    for (x in 0..10) { if (x == 2) { break; } }
+    "stopped"
+};
+[find([1, 2], 1), find("ab", "a"), find({"b": 1, "a": 2}, 0), find(0..3, 0), find(5..=5, 0), find(3..1, 0), nested(), stop()]"#;
+        assert_eq!(
+            eval(source).unwrap().to_string(),
+            r#"[2, "b", "a", 1, 5, "none", 11, "stopped"]"#
+        );
+
+        assert_eq!(eval("1..=3").unwrap().to_string(), "1..=3");
+        assert_eq!(eval("(0..2) == (0..2)"), Ok(Value::Boolean(true)));
+        assert_eq!(
+            eval("0..2 == 2"),
+            Err(AmpError::UnsupportedOperand("integer .. boolean".to_string()))
+        );
+        assert_eq!(eval("for (x in 5) {}"), Err(AmpError::NotIterable("integer")));
+        assert_eq!(
+            eval(r#""a".."b""#),
+            Err(AmpError::UnsupportedOperand("string .. string".to_string()))
+        );
+    }
+
+    #[test]
+    fn evaluates_assignments() {
+        let source = "let counter = fn() {
+    let count = 0;
+    fn() { count += 1 }
+};
+let next = counter();
+next();
+next();
+let total = 0;
+let i = 0;
+while (i < 4) {
+    i = i + 1;
+    total *= 2;
+    total += i;
+}
+let a = 0;
+let b = a = 5;
+[next(), total, a, b]";
+        assert_eq!(eval(source).unwrap().to_string(), "[3, 26, 5, 5]");
+
+        let source = r#"let grid = [[1, 2], [3, 4]];
+let copy = grid;
+grid[1][-1] = 40;
+grid[0][0] -= 10;
+let h = {"xs": [1]};
+h["xs"][0] /= 2;
+h["name"] = "a";
+h["name"] += "b";
+[grid, copy, h]"#;
+        assert_eq!(
+            eval(source).unwrap().to_string(),
+            r#"[[[-9, 2], [3, 40]], [[1, 2], [3, 4]], {"name": "ab", "xs": [0]}]"#
+        );
+
+        assert_eq!(
+            eval("let a = [1]; a[1] = 2;"),
+            Err(AmpError::IndexOutOfBounds { index: 1, len: 1 })
+        );
+        assert_eq!(
+            eval("let a = [1]; a[0][0] = 2;"),
+            Err(AmpError::NotIndexable("integer"))
+        );
+        assert_eq!(eval("let h = {}; h[[1]] = 2;"), Err(AmpError::UnhashableKey("array")));
+        assert_eq!(
+            eval(r#"let s = "a"; s -= 1;"#),
+            Err(AmpError::UnsupportedOperand("string - integer".to_string()))
+        );
+
+        let mut evaluator = Evaluator::new();
+        evaluator.eval("let a = [1, 2];").unwrap();
+        assert!(evaluator.eval("a[5] = 0;").is_err());
+        assert_eq!(evaluator.eval("a").unwrap().to_string(), "[1, 2]");
+    }
+
+    #[test]
+    fn evaluates_arrays() {
+        let source = "let a = [1, 2 * 3, fn(x) { x * 10 }];
+[a[0], a[1], a[-1](4), a[-3], [[1, 2], [3]][1][0]]";
+        assert_eq!(eval(source).unwrap().to_string(), "[1, 6, 40, 1, 3]");
+        assert_eq!(eval("[]"), Ok(Value::Array(Rc::new(Vec::new()))));
+        assert_eq!(eval("[1, [true]] == [1, [true]]"), Ok(Value::Boolean(true)));
+        assert_eq!(eval("[1, 2][2]"), Err(AmpError::IndexOutOfBounds { index: 2, len: 2 }));
+        assert_eq!(
+            eval("[1, 2][-3]"),
+            Err(AmpError::IndexOutOfBounds { index: -3, len: 2 })
+        );
+        assert_eq!(eval("5[0]"), Err(AmpError::NotIndexable("integer")));
+        assert_eq!(
+            eval("[1][true]"),
+            Err(AmpError::UnsupportedOperand("array[boolean]".to_string()))
+        );
+    }
+
+    #[test]
+    fn evaluates_hashes() {
+        let source = r#"let config = {"name": "amp", 1: true, false: [1, 2], "name": "x"};
+[config["name"], config[1], config[false][1], config["missing"], {}]"#;
+        assert_eq!(eval(source).unwrap().to_string(), r#"["x", true, 2, null, {}]"#);
+        assert_eq!(
+            eval(r#"{"b": 1, 2: "two", true: 0}"#).unwrap().to_string(),
+            r#"{2: "two", true: 0, "b": 1}"#
+        );
+        assert_eq!(eval(r#"{"a": 1} == {"a": 1}"#), Ok(Value::Boolean(true)));
+        assert_eq!(eval("{[1]: 2}"), Err(AmpError::UnhashableKey("array")));
+        assert_eq!(eval(r#"{"a": 1}[fn() {}]"#), Err(AmpError::UnhashableKey("function")));
+    }
+
+    #[test]
+    fn keeps_globals_between_runs() {
+        let mut evaluator = Evaluator::new();
+        assert_eq!(evaluator.eval("let x = 5;"), Ok(Value::Null));
+        assert_eq!(evaluator.eval("x + 1"), Ok(Value::Integer(6)));
+        assert_eq!(evaluator.global("x"), Some(&Value::Integer(5)));
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert!(matches!(eval("y"), Err(AmpError::Resolution(_))));
+        assert_eq!(eval("5(1)"), Err(AmpError::NotCallable("integer")));
+        assert_eq!(
+            eval("fn(a) { a }(1, 2)"),
+            Err(AmpError::WrongArgumentCount { expected: 1, got: 2 })
+        );
+    }
+
+    fn eval_limited(source: &str, limits: Limits) -> Result<Value, AmpError> {
+        let mut evaluator = Evaluator::new();
+        evaluator.set_limits(limits);
+        evaluator.eval(source)
+    }
+
+    /// Clock that advances by a millisecond every time it is read.
+    struct TickingClock(Cell<Duration>);
+
+    impl Clock for TickingClock {
+        fn now(&self) -> Duration {
+            let now = self.0.get();
+            self.0.set(now + Duration::from_millis(1));
+            now
+        }
+    }
+
+    #[test]
+    fn limits_call_depth() {
+        // Test threads only get 2 MiB of stack, the default depth is meant for the main thread.
+        let result = std::thread::Builder::new()
+            .stack_size(8 << 20)
+            .spawn(|| {
+                let countdown = "let down = fn(n) { if (n == 0) { 0 } else { down(n - 1) } };";
+                assert_eq!(eval(&format!("{} down(150)", countdown)), Ok(Value::Integer(0)));
+                eval("let loop = fn(n) { loop(n + 1) }; loop(0)").unwrap_err()
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(result, AmpError::CallDepthExceeded(DEFAULT_MAX_CALL_DEPTH));
+
+        let shallow = Limits {
+            max_call_depth: Some(3),
+            ..Limits::default()
+        };
+        let nested = "let f = fn(n) { if (n > 0) { f(n - 1) } else { 0 } };";
+        assert_eq!(
+            eval_limited(&format!("{} f(2)", nested), shallow.clone()),
+            Ok(Value::Integer(0))
+        );
+        assert_eq!(
+            eval_limited(&format!("{} f(3)", nested), shallow),
+            Err(AmpError::CallDepthExceeded(3))
+        );
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let forever = "let loop = fn(n) { loop(n + 1) }; loop(0)";
+        let countdown = "let down = fn(n) { if (n == 0) { 0 } else { down(n - 1) } };";
+
+        let fuel = Limits {
+            fuel: Some(100),
+            ..Limits::default()
+        };
+        assert_eq!(
+            eval_limited(&format!("{} down(5)", countdown), fuel.clone()),
+            Ok(Value::Integer(0))
+        );
+        assert_eq!(
+            eval_limited(&format!("{} down(50)", countdown), fuel),
+            Err(AmpError::OutOfFuel(100))
+        );
+
+        let memory = Limits {
+            max_memory: Some(100),
+            ..Limits::default()
+        };
+        assert_eq!(
+            eval_limited("range(101)", memory.clone()),
+            Err(AmpError::MemoryLimitExceeded(100))
+        );
+        assert_eq!(
+            eval_limited(r#"let grow = fn(s) { grow(s + s) }; grow("ab")"#, memory),
+            Err(AmpError::MemoryLimitExceeded(100))
+        );
+
+        let deadline = Limits {
+            timeout: Some(Duration::from_millis(50)),
+            clock: Rc::new(TickingClock(Cell::new(Duration::ZERO))),
+            ..Limits::unlimited()
+        };
+        assert_eq!(
+            eval_limited(forever, deadline),
+            Err(AmpError::DeadlineExceeded(Duration::from_millis(50)))
+        );
+    }
+
+    #[test]
+    fn resets_budget_for_every_run() {
+        let mut evaluator = Evaluator::new();
+        evaluator.set_limits(Limits {
+            fuel: Some(20),
+            ..Limits::default()
+        });
+        for _ in 0..3 {
+            assert_eq!(evaluator.eval("1 + 2 + 3"), Ok(Value::Integer(6)));
+        }
+        assert_eq!(evaluator.usage().steps, 6);
+        assert_eq!(evaluator.usage().call_depth, 0);
+    }
+}
@@ -0,0 +1,424 @@
+//! Canonical source formatting.
+//!
+//! The formatter reprints the token stream of a program, so comments are kept, and uses the
+//! parser to know where statements start - every statement goes on its own line. Blocks are
+//! indented by four spaces, binary operators other than the range operators `..` and `..=` are
+//! surrounded by spaces and at most one blank line is kept between statements. Hash literals stay
+//! on one line; a `{` opens a block rather than a hash when it is empty or a statement starts right
+//! after it. A comment can still break a statement, the lines after it are indented by one level
+//! for each open parenthesis, bracket or hash brace and by at least one. The output is parsed
+//! again and must produce the same tree as the input, apart from source locations, otherwise
+//! formatting fails instead of changing the program.
+use super::{
+    ast::{EKeyword, Span, Token},
+    lexer::tokenize,
+    parser::{parse_program, parse_with_spans},
+    visit::strip_locations,
+    AmpError,
+};
+use std::collections::HashSet;
+
+const INDENT: &str = "    ";
+
+pub fn format(source: &str) -> Result<String, AmpError> {
+    let (program, statement_spans) = parse_with_spans(source)?;
+    let statement_starts = statement_spans.iter().map(|span| span.start).collect();
+
+    let mut printer = Printer {
+        out: String::new(),
+        indent: 0,
+        newline: false,
+        statement_starts,
+        previous: None,
+        previous_prefix: false,
+        braces: Vec::new(),
+        groups: 0,
+        outer: Vec::new(),
+        line_indent: 0,
+    };
+    let tokens = tokenize(source);
+    for (i, (token, span)) in tokens.iter().enumerate() {
+        printer.print(token.clone(), *span, &tokens[i + 1..]);
+    }
+    let formatted = printer.finish();
+
+    if strip_locations(parse_program(&formatted)?) != strip_locations(program) {
+        return Err(AmpError::UnstableFormat);
+    }
+    Ok(formatted)
+}
+
+/// Returns whether `source` is already formatted.
+pub fn check(source: &str) -> Result<bool, AmpError> {
+    Ok(format(source)? == source)
+}
+
+struct Printer {
+    out: String,
+    indent: usize,
+    /// Set when the next token has to start on a new line.
+    newline: bool,
+    statement_starts: HashSet<usize>,
+    previous: Option<(Token, Span)>,
+    previous_prefix: bool,
+    /// Open braces, innermost last. `true` for blocks, `false` for hash literals.
+    braces: Vec<bool>,
+    /// Parentheses, square brackets and hash braces opened since the innermost block. A line that
+    /// a comment breaks inside of a statement is indented by one level for each of them, and by at
+    /// least one.
+    groups: usize,
+    /// `indent` and `groups` around each open block, innermost last.
+    outer: Vec<(usize, usize)>,
+    /// Indentation of the current line. A block is indented one level more than the line it
+    /// starts on.
+    line_indent: usize,
+}
+
+impl Printer {
+    /// Prints `token`, `rest` are the tokens after it.
+    fn print(&mut self, token: Token, span: Span, rest: &[(Token, Span)]) {
+        let previous_line = self.previous.as_ref().map(|(_, span)| span.line);
+        let blank_line = previous_line.is_some_and(|line| span.line > line + 1);
+
+        match &token {
+            Token::Comment(text) => {
+                if previous_line == Some(span.line) {
+                    self.out.push(' ');
+                } else {
+                    // A comment on a line of its own is indented like the code after it.
+                    let next = rest.iter().find(|(token, _)| !matches!(token, Token::Comment(_)));
+                    let continuation = match next {
+                        Some((Token::RightParenthesis, _)) | Some((Token::RightSquareBrace, _)) => self.groups,
+                        Some((Token::RightCurlyBrace, _)) if self.braces.last() == Some(&false) => self.groups,
+                        Some((token, span)) if !self.starts_line(token, span) => self.groups.max(1),
+                        _ => 0,
+                    };
+                    self.start_line(blank_line, continuation);
+                }
+                self.out.push_str("//");
+                self.out.push_str(text.trim_end());
+                self.newline = true;
+            }
+            Token::RightCurlyBrace if self.braces.last() == Some(&false) => {
+                self.braces.pop();
+                self.groups = self.groups.saturating_sub(1);
+                if self.newline {
+                    // After a comment.
+                    self.start_line(false, self.groups);
+                }
+                self.out.push('}');
+            }
+            Token::RightCurlyBrace => {
+                self.braces.pop();
+                self.indent = self.indent.saturating_sub(1);
+                if self.previous_token() == Some(&Token::LeftCurlyBrace) {
+                    self.newline = false;
+                } else {
+                    self.start_line(false, 0);
+                }
+                self.out.push('}');
+                (self.indent, self.groups) = self.outer.pop().unwrap_or((self.indent, 0));
+            }
+            Token::RightParenthesis | Token::RightSquareBrace => {
+                self.groups = self.groups.saturating_sub(1);
+                if self.newline {
+                    // After a comment.
+                    self.start_line(false, self.groups);
+                }
+                self.out.push_str(&token.to_string());
+            }
+            Token::SemiColon
+                if matches!(
+                    self.previous_token(),
+                    None | Some(Token::SemiColon) | Some(Token::LeftCurlyBrace)
+                ) =>
+            {
+                // Empty statement.
+                return;
+            }
+            _ => {
+                // The `if` of an `else if` is a statement of its own but stays after the `else`, the
+                // empty statement of a `;` that ends a block stays after the expression.
+                let attached =
+                    token == Token::SemiColon || self.previous_token() == Some(&Token::Keyword(EKeyword::Else));
+                if self.statement_starts.contains(&span.start) && self.previous.is_some() && !attached {
+                    self.start_line(blank_line, 0);
+                } else if self.newline {
+                    // After a comment in the middle of a statement.
+                    let continuation = if self.starts_line(&token, &span) {
+                        0
+                    } else {
+                        self.groups.max(1)
+                    };
+                    self.start_line(blank_line, continuation);
+                } else if self.previous.is_some() && self.needs_space(&token) {
+                    self.out.push(' ');
+                }
+                self.out.push_str(&token.to_string());
+            }
+        }
+
+        match token {
+            Token::LeftCurlyBrace => {
+                let block = match rest.first() {
+                    None => true,
+                    Some((token, span)) => {
+                        matches!(token, Token::RightCurlyBrace | Token::SemiColon | Token::Comment(_))
+                            || self.statement_starts.contains(&span.start)
+                    }
+                };
+                self.braces.push(block);
+                if block {
+                    self.outer.push((self.indent, self.groups));
+                    self.indent = self.line_indent + 1;
+                    self.groups = 0;
+                    self.newline = true;
+                } else {
+                    self.groups += 1;
+                }
+            }
+            Token::LeftParenthesis | Token::LeftSquareBrace => self.groups += 1,
+            Token::SemiColon => self.newline = true,
+            _ => {}
+        }
+        self.previous_prefix = matches!(token, Token::Minus | Token::Bang | Token::Tilde) && !self.ends_operand();
+        self.previous = Some((token, span));
+    }
+
+    fn finish(mut self) -> String {
+        let trimmed_len = self.out.trim_end().len();
+        self.out.truncate(trimmed_len);
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+
+    /// Starts a new line, indented by `continuation` levels more than the current block.
+    fn start_line(&mut self, blank_line: bool, continuation: usize) {
+        self.newline = false;
+        if self.out.is_empty() {
+            return;
+        }
+        self.out.push('\n');
+        if blank_line && self.previous_token() != Some(&Token::LeftCurlyBrace) {
+            self.out.push('\n');
+        }
+        self.line_indent = self.indent + continuation;
+        for _ in 0..self.line_indent {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    /// Whether `token` starts a line at the indentation of the block: it starts a statement, is
+    /// the `else` of an `if` or closes the block.
+    fn starts_line(&self, token: &Token, span: &Span) -> bool {
+        self.statement_starts.contains(&span.start)
+            || *token == Token::Keyword(EKeyword::Else)
+            || (*token == Token::RightCurlyBrace && self.braces.last() != Some(&false))
+    }
+
+    fn previous_token(&self) -> Option<&Token> {
+        self.previous.as_ref().map(|(token, _)| token)
+    }
+
+    /// Whether the previous token ends an operand, which makes a following `-` a binary operator.
+    fn ends_operand(&self) -> bool {
+        matches!(
+            self.previous_token(),
+            Some(Token::Identifier(_))
+                | Some(Token::Integer(_))
+                | Some(Token::String(_))
+                | Some(Token::RightParenthesis)
+                | Some(Token::RightSquareBrace)
+                | Some(Token::RightCurlyBrace)
+                | Some(Token::Keyword(EKeyword::True))
+                | Some(Token::Keyword(EKeyword::False))
+        )
+    }
+
+    fn needs_space(&self, token: &Token) -> bool {
+        if self.previous_prefix {
+            return false;
+        }
+        let previous = match self.previous_token() {
+            Some(previous) => previous,
+            None => return false,
+        };
+        match (previous, token) {
+            (Token::LeftParenthesis, _) | (Token::LeftSquareBrace, _) => false,
+            (Token::LeftCurlyBrace, _) => self.braces.last() != Some(&false),
+            (_, Token::RightParenthesis) | (_, Token::RightSquareBrace) => false,
+            (_, Token::Comma) | (_, Token::SemiColon) | (_, Token::Colon) => false,
+            (Token::DotDot, _) | (Token::DotDotEqual, _) | (_, Token::DotDot) | (_, Token::DotDotEqual) => false,
+            (Token::Keyword(EKeyword::Function), Token::LeftParenthesis) => false,
+            (_, Token::LeftParenthesis) | (_, Token::LeftSquareBrace) => !self.ends_operand(),
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_formats(input: &str, expected: &str) {
+        let formatted = format(input).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted).unwrap(), formatted, "formatting is not idempotent");
+        assert!(check(&formatted).unwrap());
+    }
+
+    #[test]
+    fn formats_blocks_and_else() {
+        assert_formats(
+            "if (x) {
+    return 15;
+}    else {
+    return 30;
+}
+",
+            "if (x) {
+    return 15;
+} else {
+    return 30;
+}
+",
+        );
+        assert_formats(
+            "let y = if (x) { 1 } else if (z) { 2 }\nelse   if (w) {} else { 3 };",
+            "let y = if (x) {
+    1
+} else if (z) {
+    2
+} else if (w) {} else {
+    3
+};
+",
+        );
+    }
+
+    #[test]
+    fn formats_operators_and_statements() {
+        assert_formats(
+            "let   a=-1+2*3;let b=!true;a==b;add( a,b );",
+            "let a = -1 + 2 * 3;
+let b = !true;
+a == b;
+add(a, b);
+",
+        );
+        assert_formats("a - -b;x-1", "a - -b;\nx - 1\n");
+        assert_formats("let a=[ 1,-2,[3] ];a [0][-1]", "let a = [1, -2, [3]];\na[0][-1]\n");
+        assert_formats(
+            "for(i in 0 ..= n-1){puts(i)}",
+            "for (i in 0..=n - 1) {\n    puts(i)\n}\n",
+        );
+        assert_formats("x+=1;a[0]=b=-2", "x += 1;\na[0] = b = -2\n");
+        assert_formats("a&&!b||c", "a && !b || c\n");
+        assert_formats("x%2**-n|~y<<1", "x % 2 ** -n | ~y << 1\n");
+    }
+
+    #[test]
+    fn formats_functions() {
+        assert_formats(
+            "let add=fn(x,y){x+y};let noop = fn() {};
+let twice = fn(f, x) { f(f(x)) }; twice(fn(x){x*2}, 3);fn(){5}()",
+            "let add = fn(x, y) {
+    x + y
+};
+let noop = fn() {};
+let twice = fn(f, x) {
+    f(f(x))
+};
+twice(fn(x) {
+    x * 2
+}, 3);
+fn() {
+    5
+}()
+",
+        );
+    }
+
+    #[test]
+    fn formats_hashes_and_strings() {
+        assert_formats(
+            r#"let h={"a" :1,2:"two\n",true:{ }};h [ "a" ];let f = fn() { {"k": [1]} };"#,
+            r#"let h = {"a": 1, 2: "two\n", true: {}};
+h["a"];
+let f = fn() {
+    {"k": [1]}
+};
+"#,
+        );
+        assert_formats("{\"a\": 1 // one\n}", "{\"a\": 1 // one\n}\n");
+    }
+
+    #[test]
+    fn formats_block_expressions() {
+        assert_formats(
+            "let y={let x=1;x*2};{f(y) ;}{x}",
+            "let y = {
+    let x = 1;
+    x * 2
+};
+{
+    f(y);
+}
+{
+    x
+}
+",
+        );
+    }
+
+    #[test]
+    fn preserves_comments_and_blank_lines() {
+        assert_formats(
+            "// header
+let x = 1; // one
+
+
+let f = fn(a) {
+  // inside
+  a
+};
+",
+            "// header
+let x = 1; // one
+
+let f = fn(a) {
+    // inside
+    a
+};
+",
+        );
+    }
+
+    #[test]
+    fn indents_lines_after_comments_inside_expressions() {
+        assert_formats("let a = [\n  1, // one\n  2\n];", "let a = [1, // one\n    2];\n");
+        assert_formats("f(1, // first\n  2);", "f(1, // first\n    2);\n");
+        assert_formats(
+            "let h = {\"a\": 1, // a\n\"b\": 2};",
+            "let h = {\"a\": 1, // a\n    \"b\": 2};\n",
+        );
+        assert_formats("let x = 1 // c\n+ 2;", "let x = 1 // c\n    + 2;\n");
+        assert_formats(
+            "[\n// leading\n1, [2, // two\n3\n]\n]",
+            "[\n    // leading\n    1, [2, // two\n        3]]\n",
+        );
+        assert_formats(
+            "let f = fn() {\ng(1, // c\nfn() { x });\n};",
+            "let f = fn() {\n    g(1, // c\n        fn() {\n            x\n        });\n};\n",
+        );
+    }
+
+    #[test]
+    fn check_reports_unformatted_sources() {
+        assert!(!check("let x=1;").unwrap());
+        assert!(check("let x = 1;\n").unwrap());
+        assert!(format("let x = ;").is_err());
+    }
+}
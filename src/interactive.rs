@@ -1,20 +1,234 @@
-use super::parser::parse_program;
-use std::io::{self, Write};
+use super::{ast::Token, eval::Evaluator, lexer::tokenize, parser::parse_program, value::Value, AmpError};
+use rustyline::{error::ReadlineError, DefaultEditor};
+use std::{env, fs, io, path::PathBuf};
+
+const PROMPT: &str = "=> ";
+const CONTINUATION_PROMPT: &str = ".. ";
+const HISTORY_FILE: &str = ".amp_history";
+const HELP: &str = ":tokens <src>  print the tokens of <src>
+:ast <src>     print the syntax tree of <src>
+:env           list global bindings
+:load <file>   run a file in the current session
+:reset         forget all bindings
+:type <expr>   print the type of the value of <expr> without running its effects
+:quit          leave the REPL";
 
 pub struct AmpCli {}
 impl AmpCli {
     pub fn run() {
         println!("AmpCli v0.1.0");
-        loop {
-            print!("=> ");
-            io::stdout().flush().unwrap();
-            let mut input = String::new();
-            if let Err(e) = io::stdin().read_line(&mut input) {
-                println!("Invalid input line '{}' - '{}'", input, e);
-                continue;
+        let mut editor = match DefaultEditor::new() {
+            Ok(editor) => editor,
+            Err(e) => {
+                eprintln!("failed to initialize line editor - '{}'", e);
+                return;
+            }
+        };
+        let history = history_path();
+        if let Some(path) = &history {
+            // The file doesn't exist on the first run.
+            let _ = editor.load_history(path);
+        }
+
+        let mut session = Session::new();
+        let mut input = String::new();
+        while !session.is_done() {
+            let prompt = if input.is_empty() { PROMPT } else { CONTINUATION_PROMPT };
+            match editor.readline(prompt) {
+                Ok(line) => {
+                    input.push_str(&line);
+                    input.push('\n');
+                    if !is_complete(&input) {
+                        continue;
+                    }
+                    if !input.trim().is_empty() {
+                        let _ = editor.add_history_entry(input.trim_end());
+                        let output = session.handle(&input);
+                        if !output.is_empty() {
+                            println!("{}", output);
+                        }
+                    }
+                    input.clear();
+                }
+                // Ctrl-C drops the current input, Ctrl-D quits.
+                Err(ReadlineError::Interrupted) => input.clear(),
+                Err(ReadlineError::Eof) => break,
+                Err(e) => {
+                    eprintln!("Invalid input line '{}' - '{}'", input, e);
+                    break;
+                }
+            }
+        }
+
+        if let Some(path) = &history {
+            if let Err(e) = editor.save_history(path) {
+                eprintln!("failed to save history to '{}' - '{}'", path.display(), e);
+            }
+        }
+    }
+}
+
+/// REPL state that outlives a single input: global bindings and whether `:quit` was entered.
+#[derive(Default)]
+pub struct Session {
+    evaluator: Evaluator,
+    done: bool,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Runs one complete input, either a meta-command starting with `:` or source code, and
+    /// returns the text to show. `null` results print nothing.
+    pub fn handle(&mut self, input: &str) -> String {
+        let input = input.trim();
+        let (command, argument) = match input.strip_prefix(':') {
+            Some(command) => {
+                let mut parts = command.splitn(2, char::is_whitespace);
+                (parts.next().unwrap_or(""), parts.next().unwrap_or("").trim())
+            }
+            None => return self.eval(input),
+        };
+
+        match command {
+            "tokens" => {
+                let tokens: Vec<String> = tokenize(argument)
+                    .iter()
+                    .map(|(token, _)| format!("{:?}", token))
+                    .collect();
+                tokens.join("\n")
+            }
+            "ast" => match parse_program(argument) {
+                Ok(program) => format!("{:#?}", program),
+                Err(e) => format!("error: {}", e),
+            },
+            "env" => {
+                let globals: Vec<String> = self
+                    .evaluator
+                    .globals()
+                    .iter()
+                    .map(|(name, value)| format!("{} = {}", name, value))
+                    .collect();
+                globals.join("\n")
+            }
+            "load" => match fs::read_to_string(argument) {
+                Ok(source) => self.eval(&source),
+                Err(e) => format!("error: failed to read '{}' - {}", argument, e),
+            },
+            "reset" => {
+                self.evaluator = Evaluator::new();
+                String::new()
             }
+            // Runs on a copy of the session that discards its output.
+            "type" => match self.evaluator.fork(Box::new(io::sink())).eval(argument) {
+                Ok(value) => value.type_name().to_string(),
+                Err(e) => error_message(&e),
+            },
+            "quit" | "q" => {
+                self.done = true;
+                String::new()
+            }
+            "help" => HELP.to_string(),
+            _ => format!("error: unknown command ':{}', try :help", command),
+        }
+    }
+
+    fn eval(&mut self, source: &str) -> String {
+        match self.evaluator.eval(source) {
+            Ok(Value::Null) => String::new(),
+            Ok(value) => value.to_string(),
+            Err(e) => error_message(&e),
+        }
+    }
+}
+
+/// Resolution errors are diagnostics that already carry their severity.
+fn error_message(e: &AmpError) -> String {
+    match e {
+        AmpError::Resolution(_) => e.to_string(),
+        e => format!("error: {}", e),
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
 
-            println!("{:?}", parse_program(&input))
+/// Returns whether all braces, brackets and parentheses opened in `input` are closed. Unbalanced
+/// closing tokens count as complete so the parser can report them.
+pub(crate) fn is_complete(input: &str) -> bool {
+    let mut depth: isize = 0;
+    for (token, _) in tokenize(input) {
+        match token {
+            Token::LeftCurlyBrace | Token::LeftParenthesis | Token::LeftSquareBrace => depth += 1,
+            Token::RightCurlyBrace | Token::RightParenthesis | Token::RightSquareBrace => depth -= 1,
+            _ => {}
+        }
+        if depth < 0 {
+            return true;
         }
     }
+    depth == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_incomplete_input() {
+        assert!(is_complete("let x = 5;\n"));
+        assert!(is_complete(""));
+        assert!(!is_complete("let add = fn(x, y) {\n"));
+        assert!(!is_complete("let add = fn(x, y) {\n    x + y;\n"));
+        assert!(is_complete("let add = fn(x, y) {\n    x + y;\n};\n"));
+        assert!(!is_complete("add(1,\n"));
+        assert!(!is_complete("// comment with { brace\nif (x) {\n"));
+        assert!(is_complete("x; }\n"));
+    }
+
+    #[test]
+    fn keeps_state_between_inputs() {
+        let mut session = Session::new();
+        assert_eq!(session.handle("let x = 5;\n"), "");
+        assert_eq!(session.handle("x + 1\n"), "6");
+        assert_eq!(session.handle("let add = fn(a, b) { a + b };"), "");
+        assert_eq!(session.handle("add(x, 2)"), "7");
+        assert_eq!(session.handle("y"), "1:1: error: undefined variable `y`");
+        assert_eq!(session.handle("1 / 0"), "error: division by zero");
+        assert_eq!(session.handle(":env"), "add = fn(a, b) { ... }\nx = 5");
+        assert_eq!(session.handle(":reset"), "");
+        assert_eq!(session.handle(":env"), "");
+    }
+
+    #[test]
+    fn runs_meta_commands() {
+        let mut session = Session::new();
+        assert_eq!(session.handle(":tokens let x"), "Keyword(Let)\nIdentifier(\"x\")");
+        assert!(session.handle(":ast 1 + 2").starts_with("[\n    Expression("));
+        assert_eq!(session.handle(":type 1 < 2"), "boolean");
+        assert_eq!(session.handle(":type fn() {}"), "function");
+        assert_eq!(session.handle(":type y"), "1:1: error: undefined variable `y`");
+
+        // `:type` leaves bindings alone, including the ones captured by closures.
+        session.handle("let x = 5; let next = fn() { let n = 0; fn() { n += 1; n } }();");
+        assert_eq!(session.handle(":type x = \"five\""), "string");
+        assert_eq!(session.handle(":type next()"), "integer");
+        assert_eq!(session.handle(":type puts(x)"), "null");
+        assert_eq!(session.handle("x"), "5");
+        assert_eq!(session.handle("next()"), "1");
+        assert_eq!(session.handle(":nope"), "error: unknown command ':nope', try :help");
+        assert!(session
+            .handle(":load /nonexistent.amp")
+            .starts_with("error: failed to read"));
+        assert!(!session.is_done());
+        session.handle(":quit");
+        assert!(session.is_done());
+    }
 }
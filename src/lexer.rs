@@ -1,49 +1,80 @@
 use super::{
-    ast::{EKeyword, Token},
+    ast::{EKeyword, Span, Token},
     reader::Reader,
 };
 
 pub(crate) struct Lexer<'r> {
     reader: Reader<'r>,
+    span: Span,
 }
 impl<'r> Lexer<'r> {
     pub(crate) fn new(source: &'r str) -> Lexer<'r> {
         Lexer {
             reader: Reader::new(source),
+            span: Span::default(),
         }
     }
 
     pub(crate) fn next_token(&mut self) -> Token {
+        self.reader.skip_whitespace();
+        let start = self.reader.position();
+        let (line, column) = (self.reader.line(), self.reader.column());
         let token = match self.reader.current() {
-            ch @ '{'
-            | ch @ '}'
-            | ch @ '['
-            | ch @ ']'
-            | ch @ '('
-            | ch @ ')'
-            | ch @ ','
-            | ch @ '+'
-            | ch @ ';'
-            | ch @ '*'
-            | ch @ '-'
-            | ch @ '/' => {
+            None => Token::EOF,
+            Some(ch) => self.parse_token(ch),
+        };
+        self.span = Span {
+            start,
+            end: self.reader.position(),
+            line,
+            column,
+        };
+        token
+    }
+
+    /// Span of the token most recently returned by `next_token`.
+    pub(crate) fn span(&self) -> Span {
+        self.span
+    }
+
+    fn parse_token(&mut self, ch: char) -> Token {
+        match ch {
+            '/' if self.reader.peek() == Some('/') => self.parse_comment(),
+            '{' | '}' | '[' | ']' | '(' | ')' | ',' | ':' | ';' | '%' | '^' | '~' => {
                 self.reader.skip(1);
                 Token::from_char(ch).unwrap()
             }
+            '+' => self.parse_double_or_single('=', Token::PlusAssign, Token::Plus),
+            '-' => self.parse_double_or_single('=', Token::MinusAssign, Token::Minus),
+            '*' if self.reader.peek() == Some('*') => {
+                self.reader.skip(2);
+                Token::Power
+            }
+            '*' => self.parse_double_or_single('=', Token::AsteriskAssign, Token::Asterisk),
+            '/' => self.parse_double_or_single('=', Token::SlashAssign, Token::Slash),
             '!' => self.parse_double_or_single('=', Token::NotEqual, Token::Bang),
+            '&' => self.parse_double_or_single('&', Token::And, Token::Ampersand),
+            '|' => self.parse_double_or_single('|', Token::Or, Token::Pipe),
+            '<' if self.reader.peek() == Some('<') => {
+                self.reader.skip(2);
+                Token::ShiftLeft
+            }
             '<' => self.parse_double_or_single('=', Token::LessThanOrEqual, Token::LessThan),
+            '>' if self.reader.peek() == Some('>') => {
+                self.reader.skip(2);
+                Token::ShiftRight
+            }
             '>' => self.parse_double_or_single('=', Token::GreaterThanOrEqual, Token::GreaterThan),
             '=' => self.parse_double_or_single('=', Token::Equal, Token::Assign),
-            _ if self.is_last() => Token::EOF,
-            ch if ch.is_ascii_whitespace() => {
-                self.reader.skip_whitespace();
-                self.next_token()
-            }
+            '.' => self.parse_dots(),
+            '"' => self.parse_string(),
             ch if ch.is_ascii_digit() => self.parse_number(),
             ch if ch.is_ascii_alphabetic() || ch == '_' => self.parse_ident_or_keyword(),
-            ch => Token::Invalid(ch.to_string()),
-        };
-        token
+            ch => {
+                self.reader.skip(1);
+                Token::Invalid(ch.to_string())
+            }
+        }
     }
 
     fn parse_double_or_single(&mut self, second_char: char, double: Token, single: Token) -> Token {
@@ -57,23 +88,103 @@ impl<'r> Lexer<'r> {
         single
     }
 
+    /// Parses `..` or `..=`, a single `.` is invalid.
+    fn parse_dots(&mut self) -> Token {
+        if self.reader.peek() != Some('.') {
+            self.reader.skip(1);
+            return Token::Invalid(".".to_string());
+        }
+        self.reader.skip(2);
+        if self.reader.current() == Some('=') {
+            self.reader.skip(1);
+            return Token::DotDotEqual;
+        }
+        Token::DotDot
+    }
+
+    fn parse_comment(&mut self) -> Token {
+        self.reader.skip(2);
+        let mut comment = String::new();
+        while let Some(ch) = self.reader.current() {
+            if ch == '\n' {
+                break;
+            }
+            comment.push(ch);
+            self.reader.next();
+        }
+
+        Token::Comment(comment)
+    }
+
+    /// Parses a string literal. Supports the `\"`, `\\`, `\n`, `\t` and `\r` escapes, an unknown
+    /// escape or a missing closing quote makes the literal invalid.
+    fn parse_string(&mut self) -> Token {
+        let start = self.reader.position();
+        let mut text = String::new();
+        let mut valid = true;
+        self.reader.next();
+        loop {
+            match self.reader.current() {
+                None => {
+                    valid = false;
+                    break;
+                }
+                Some('"') => {
+                    self.reader.next();
+                    break;
+                }
+                Some('\\') => {
+                    let escaped = match self.reader.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        _ => {
+                            valid = false;
+                            continue;
+                        }
+                    };
+                    text.push(escaped);
+                    self.reader.next();
+                }
+                Some(ch) => {
+                    text.push(ch);
+                    self.reader.next();
+                }
+            }
+        }
+
+        if valid {
+            Token::String(text)
+        } else {
+            Token::Invalid(self.reader.slice(start, self.reader.position()).to_string())
+        }
+    }
+
+    /// Parses a decimal literal, one that doesn't fit into `u64` is invalid.
     fn parse_number(&mut self) -> Token {
-        let mut num = format!("{}", self.reader.current());
-        while let Some(ch) = self.reader.next() {
+        let mut num = String::new();
+        while let Some(ch) = self.reader.current() {
             if !ch.is_ascii_digit() {
                 break;
             }
             num.push(ch);
+            self.reader.next();
         }
 
-        Token::Integer(num.parse().unwrap())
+        match num.parse() {
+            Ok(n) => Token::Integer(n),
+            Err(_) => Token::Invalid(num),
+        }
     }
 
     fn parse_ident_or_keyword(&mut self) -> Token {
-        let mut ident = format!("{}", self.reader.current());
-        while let Some(ch) = self.reader.next() {
+        let mut ident = String::new();
+        while let Some(ch) = self.reader.current() {
             if ch.is_ascii_alphabetic() || ch == '_' {
                 ident.push(ch);
+                self.reader.next();
             } else {
                 break;
             }
@@ -87,13 +198,27 @@ impl<'r> Lexer<'r> {
             "true" => Token::Keyword(EKeyword::True),
             "false" => Token::Keyword(EKeyword::False),
             "return" => Token::Keyword(EKeyword::Return),
+            "while" => Token::Keyword(EKeyword::While),
+            "break" => Token::Keyword(EKeyword::Break),
+            "continue" => Token::Keyword(EKeyword::Continue),
+            "for" => Token::Keyword(EKeyword::For),
+            "in" => Token::Keyword(EKeyword::In),
             _ => Token::Identifier(ident),
         }
     }
+}
 
-    pub(crate) fn is_last(&self) -> bool {
-        self.reader.is_last()
+/// Splits `source` into tokens with their spans, including comments. The trailing `EOF` is omitted.
+pub fn tokenize(source: &str) -> Vec<(Token, Span)> {
+    let mut lexer = Lexer::new(source);
+    let mut tokens = Vec::new();
+    loop {
+        match lexer.next_token() {
+            Token::EOF => break,
+            token => tokens.push((token, lexer.span())),
+        }
     }
+    tokens
 }
 
 #[cfg(test)]
@@ -102,7 +227,7 @@ mod tests {
 
     #[test]
     fn parses_simple_tokens() {
-        let input = "{}[]()+=,";
+        let input = "{}[]()+ =,+=-=*=/=&&||%***&|^~<<<=>>>=";
         let expected = vec![
             Token::LeftCurlyBrace,
             Token::RightCurlyBrace,
@@ -113,6 +238,23 @@ mod tests {
             Token::Plus,
             Token::Assign,
             Token::Comma,
+            Token::PlusAssign,
+            Token::MinusAssign,
+            Token::AsteriskAssign,
+            Token::SlashAssign,
+            Token::And,
+            Token::Or,
+            Token::Percent,
+            Token::Power,
+            Token::Asterisk,
+            Token::Ampersand,
+            Token::Pipe,
+            Token::Caret,
+            Token::Tilde,
+            Token::ShiftLeft,
+            Token::LessThanOrEqual,
+            Token::ShiftRight,
+            Token::GreaterThanOrEqual,
         ];
 
         let mut l = Lexer::new(input);
@@ -272,4 +414,119 @@ let is_smaller = fn(x, y) {
             assert_eq!(l.next_token(), token);
         }
     }
+
+    #[test]
+    fn parses_last_token_and_spans() {
+        let input = "let x = 5;\n  x";
+        let mut l = Lexer::new(input);
+        let mut tokens = Vec::new();
+        loop {
+            let token = l.next_token();
+            if token == Token::EOF {
+                break;
+            }
+            tokens.push((token, l.span()));
+        }
+
+        assert_eq!(tokens.len(), 6);
+        assert_eq!(
+            tokens[5],
+            (
+                Token::Identifier("x".to_string()),
+                Span {
+                    start: 13,
+                    end: 14,
+                    line: 2,
+                    column: 3
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn parses_comments() {
+        let input = "x / y; // divide\n// done";
+        let tokens: Vec<Token> = tokenize(input).into_iter().map(|(token, _)| token).collect();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Identifier("x".to_string()),
+                Token::Slash,
+                Token::Identifier("y".to_string()),
+                Token::SemiColon,
+                Token::Comment(" divide".to_string()),
+                Token::Comment(" done".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_strings() {
+        let input = r#"{"name": "a \"b\"\n", "zażółć"} "\x" "open"#;
+        let tokens: Vec<Token> = tokenize(input).into_iter().map(|(token, _)| token).collect();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::LeftCurlyBrace,
+                Token::String("name".to_string()),
+                Token::Colon,
+                Token::String("a \"b\"\n".to_string()),
+                Token::Comma,
+                Token::String("zażółć".to_string()),
+                Token::RightCurlyBrace,
+                Token::Invalid(r#""\x""#.to_string()),
+                Token::Invalid(r#""open"#.to_string()),
+            ]
+        );
+        assert_eq!(Token::String("a \"b\"\n".to_string()).to_string(), r#""a \"b\"\n""#);
+    }
+
+    #[test]
+    fn handles_non_ascii_and_huge_numbers() {
+        let input = "// zażółć\nłoś 99999999999999999999 18446744073709551615";
+        let tokens = tokenize(input);
+
+        assert_eq!(
+            tokens.iter().map(|(token, _)| token.clone()).collect::<Vec<_>>(),
+            vec![
+                Token::Comment(" zażółć".to_string()),
+                Token::Invalid("ł".to_string()),
+                Token::Identifier("o".to_string()),
+                Token::Invalid("ś".to_string()),
+                Token::Invalid("99999999999999999999".to_string()),
+                Token::Integer(u64::MAX),
+            ]
+        );
+        assert_eq!((tokens[2].1.start, tokens[2].1.column), (16, 3));
+    }
+
+    #[test]
+    fn lexes_ranges() {
+        let tokens: Vec<Token> = tokenize("for (i in 0..n) 1..=2 . ...")
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Keyword(EKeyword::For),
+                Token::LeftParenthesis,
+                Token::Identifier("i".to_string()),
+                Token::Keyword(EKeyword::In),
+                Token::Integer(0),
+                Token::DotDot,
+                Token::Identifier("n".to_string()),
+                Token::RightParenthesis,
+                Token::Integer(1),
+                Token::DotDotEqual,
+                Token::Integer(2),
+                Token::Invalid(".".to_string()),
+                Token::DotDot,
+                Token::Invalid(".".to_string()),
+            ]
+        );
+    }
 }
@@ -1,9 +1,22 @@
-mod ast;
+pub mod ast;
+pub mod builtins;
+pub mod diagnostic;
+pub mod dump;
+pub mod engine;
+pub mod eval;
+pub mod formatter;
 pub mod interactive;
 mod lexer;
+pub mod limits;
+pub mod lint;
+pub mod lsp;
 mod parser;
 mod reader;
-pub use parser::{parses_if_else, parses_let_statement, parses_prefix_expression};
+pub mod resolver;
+pub mod value;
+pub mod visit;
+pub use lexer::tokenize;
+pub use parser::{parse_program, parse_program_with_max_nesting, DEFAULT_MAX_NESTING};
 
 use thiserror::Error;
 
@@ -13,4 +26,73 @@ pub enum AmpError {
     InvalidToken(ast::Token, ast::Token),
     #[error("missing expression '{0}'")]
     MissingExpression(String),
+    #[error("unexpected token '{0:?}'")]
+    UnexpectedToken(ast::Token),
+    #[error("expressions nested deeper than {limit} levels at {line}:{column}")]
+    NestingTooDeep { limit: usize, line: usize, column: usize },
+    #[error("invalid assignment target")]
+    InvalidAssignmentTarget,
+    #[error("unknown lint rule '{0}'")]
+    UnknownLint(String),
+    #[error("formatting would change the meaning of the program")]
+    UnstableFormat,
+    #[error("{}", display_diagnostics(.0))]
+    Resolution(Vec<diagnostic::Diagnostic>),
+    #[error("undefined variable '{0}'")]
+    UndefinedVariable(String),
+    #[error("unsupported operand types for '{0}'")]
+    UnsupportedOperand(String),
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("integer overflow")]
+    IntegerOverflow,
+    #[error("negative exponent {0}")]
+    NegativeExponent(i64),
+    #[error("shift amount {0} is outside of 0..64")]
+    InvalidShift(i64),
+    #[error("value of type '{0}' is not callable")]
+    NotCallable(&'static str),
+    #[error("index {index} out of bounds for length {len}")]
+    IndexOutOfBounds { index: i64, len: usize },
+    #[error("value of type '{0}' can't be used as a hash key")]
+    UnhashableKey(&'static str),
+    #[error("value of type '{0}' is not iterable")]
+    NotIterable(&'static str),
+    #[error("value of type '{0}' can't be indexed")]
+    NotIndexable(&'static str),
+    #[error("`{name}` expects {expected}, got {got}")]
+    ArgumentCount { name: String, expected: String, got: usize },
+    #[error("invalid argument {position} to `{name}`: {message}")]
+    InvalidArgument {
+        name: String,
+        position: usize,
+        message: String,
+    },
+    #[error("`{name}` failed: {message}")]
+    Host { name: String, message: String },
+    #[error("expected {expected}, got {got}")]
+    TypeMismatch { expected: &'static str, got: &'static str },
+    #[error("out of fuel after {0} steps")]
+    OutOfFuel(u64),
+    #[error("maximum call depth of {0} exceeded")]
+    CallDepthExceeded(usize),
+    #[error("memory limit of {0} exceeded")]
+    MemoryLimitExceeded(usize),
+    #[error("deadline of {0:?} exceeded")]
+    DeadlineExceeded(std::time::Duration),
+    #[error("`{0}` outside of a loop")]
+    OutsideLoop(&'static str),
+    #[error("i/o error: {0}")]
+    Io(String),
+    #[error("wrong number of arguments, expected {expected} got {got}")]
+    WrongArgumentCount { expected: usize, got: usize },
+}
+
+fn display_diagnostics(diagnostics: &[diagnostic::Diagnostic]) -> String {
+    let lines: Vec<String> = diagnostics
+        .iter()
+        .filter(|d| d.is_error())
+        .map(|d| d.to_string())
+        .collect();
+    lines.join("\n")
 }
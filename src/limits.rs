@@ -0,0 +1,93 @@
+//! Resource limits for running untrusted programs.
+//!
+//! Fuel, memory and the timeout are budgets for one run, i.e. a single call to `Evaluator::eval`,
+//! `Evaluator::eval_program` or `Engine::call_fn`. Exceeding a limit stops the run with
+//! `AmpError::OutOfFuel`, `AmpError::CallDepthExceeded`, `AmpError::MemoryLimitExceeded` or
+//! `AmpError::DeadlineExceeded`.
+use std::{
+    fmt,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+/// Call depth allowed by default. Every call nests several native frames of the tree-walking
+/// evaluator, this depth fits into the 8 MiB main thread stack even in debug builds.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 200;
+
+/// Source of time for `Limits::timeout`, replaceable in tests.
+pub trait Clock {
+    /// Time elapsed since an arbitrary fixed point.
+    fn now(&self) -> Duration;
+}
+
+/// Monotonic clock backed by `Instant`.
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock { start: Instant::now() }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+#[derive(Clone)]
+pub struct Limits {
+    /// Evaluation steps, one per statement and expression.
+    pub fuel: Option<u64>,
+    /// Nesting of function calls.
+    pub max_call_depth: Option<usize>,
+    /// Bytes of strings plus elements of arrays and hashes created by the program.
+    pub max_memory: Option<usize>,
+    /// Wall-clock time a run may take.
+    pub timeout: Option<Duration>,
+    pub clock: Rc<dyn Clock>,
+}
+
+impl Limits {
+    /// No limits at all, not even on the call depth.
+    pub fn unlimited() -> Limits {
+        Limits {
+            max_call_depth: None,
+            ..Limits::default()
+        }
+    }
+}
+
+/// Only the call depth is limited, to `DEFAULT_MAX_CALL_DEPTH`.
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            fuel: None,
+            max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH),
+            max_memory: None,
+            timeout: None,
+            clock: Rc::new(SystemClock::default()),
+        }
+    }
+}
+
+impl fmt::Debug for Limits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Limits")
+            .field("fuel", &self.fuel)
+            .field("max_call_depth", &self.max_call_depth)
+            .field("max_memory", &self.max_memory)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+/// Resources used by the current run.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Usage {
+    pub steps: u64,
+    pub call_depth: usize,
+    pub memory: usize,
+}
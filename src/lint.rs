@@ -0,0 +1,359 @@
+//! Configurable static checks for amp programs.
+//!
+//! Every rule has a name and a default `Level`. Levels can be changed through `LintConfig` and a
+//! file can silence rules for itself with a comment anywhere in the source:
+//!
+//! ```text
+//! // amp-lint: allow(unused_let, shadowed_variable)
+//! ```
+use super::{
+    ast::{Expr, Span, Statement, Token},
+    diagnostic::Diagnostic,
+    lexer::tokenize,
+    parser::parse_with_spans,
+    resolver::{BindingKind, Resolution, Resolver},
+    visit::{walk_expr, walk_statement, Visitor},
+    AmpError,
+};
+use std::collections::HashMap;
+
+pub const UNUSED_LET: &str = "unused_let";
+pub const UNUSED_PARAMETER: &str = "unused_parameter";
+pub const SHADOWED_VARIABLE: &str = "shadowed_variable";
+pub const UNREACHABLE_CODE: &str = "unreachable_code";
+pub const BOOL_COMPARISON: &str = "bool_comparison";
+pub const EMPTY_IF: &str = "empty_if";
+
+const ALLOW_DIRECTIVE: &str = "amp-lint:";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Level {
+    /// The rule is not checked.
+    Allow,
+    /// Violations are reported as warnings.
+    Warn,
+    /// Violations are reported as errors.
+    Deny,
+}
+
+#[derive(Debug)]
+pub struct Rule {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub default: Level,
+}
+
+pub const RULES: &[Rule] = &[
+    Rule {
+        name: UNUSED_LET,
+        description: "a `let` binding that is never used",
+        default: Level::Warn,
+    },
+    Rule {
+        name: UNUSED_PARAMETER,
+        description: "a function parameter that is never used",
+        default: Level::Warn,
+    },
+    Rule {
+        name: SHADOWED_VARIABLE,
+        description: "a binding that hides another binding with the same name",
+        default: Level::Warn,
+    },
+    Rule {
+        name: UNREACHABLE_CODE,
+        description: "a statement following a `return`, `break` or `continue` in the same block",
+        default: Level::Warn,
+    },
+    Rule {
+        name: BOOL_COMPARISON,
+        description: "a comparison with `true` or `false`",
+        default: Level::Warn,
+    },
+    Rule {
+        name: EMPTY_IF,
+        description: "an `if` with an empty consequence",
+        default: Level::Warn,
+    },
+];
+
+#[derive(Debug, Clone)]
+pub struct LintConfig {
+    levels: HashMap<&'static str, Level>,
+}
+
+impl Default for LintConfig {
+    fn default() -> LintConfig {
+        LintConfig {
+            levels: RULES.iter().map(|rule| (rule.name, rule.default)).collect(),
+        }
+    }
+}
+
+impl LintConfig {
+    pub fn new() -> LintConfig {
+        LintConfig::default()
+    }
+
+    pub fn set(&mut self, rule: &str, level: Level) -> Result<(), AmpError> {
+        match RULES.iter().find(|r| r.name == rule) {
+            Some(r) => {
+                self.levels.insert(r.name, level);
+                Ok(())
+            }
+            None => Err(AmpError::UnknownLint(rule.to_string())),
+        }
+    }
+
+    pub fn level(&self, rule: &str) -> Level {
+        self.levels.get(rule).copied().unwrap_or(Level::Allow)
+    }
+}
+
+/// Parses, resolves and lints `source`. Resolution errors are returned along with the lints,
+/// sorted by their location.
+pub fn lint(source: &str, config: &LintConfig) -> Result<Vec<Diagnostic>, AmpError> {
+    let (mut program, statement_spans) = parse_with_spans(source)?;
+    let resolution = Resolver::new().resolve(&mut program);
+
+    let mut checker = Checker {
+        config: config.clone(),
+        statement_spans,
+        next_statement: 0,
+        diagnostics: resolution.diagnostics.clone(),
+    };
+    checker.apply_allow_comments(source);
+    checker.check_bindings(&resolution);
+    checker.visit_block(&program);
+
+    let mut diagnostics = checker.diagnostics;
+    diagnostics.sort_by_key(|d| d.span.map(|span| span.start));
+    Ok(diagnostics)
+}
+
+struct Checker {
+    config: LintConfig,
+    statement_spans: Vec<Span>,
+    /// Index of the next statement in the pre-order walk, used to look up its span.
+    next_statement: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn report(&mut self, rule: &'static str, message: String, span: Option<Span>) {
+        let diagnostic = match self.config.level(rule) {
+            Level::Allow => return,
+            Level::Warn => Diagnostic::warning(message, span),
+            Level::Deny => Diagnostic::error(message, span),
+        };
+        self.diagnostics.push(diagnostic.with_code(rule));
+    }
+
+    fn apply_allow_comments(&mut self, source: &str) {
+        for (token, span) in tokenize(source) {
+            let comment = match token {
+                Token::Comment(comment) => comment,
+                _ => continue,
+            };
+            let rules = comment
+                .trim()
+                .strip_prefix(ALLOW_DIRECTIVE)
+                .map(str::trim)
+                .and_then(|directive| directive.strip_prefix("allow("))
+                .and_then(|directive| directive.strip_suffix(')'));
+            for rule in rules.into_iter().flat_map(|rules| rules.split(',')).map(str::trim) {
+                if let Err(e) = self.config.set(rule, Level::Allow) {
+                    self.diagnostics.push(Diagnostic::warning(e.to_string(), Some(span)));
+                }
+            }
+        }
+    }
+
+    fn check_bindings(&mut self, resolution: &Resolution) {
+        for (i, binding) in resolution.bindings.iter().enumerate() {
+            let span = binding
+                .declaration
+                .and_then(|occurrence| resolution.occurrences[occurrence].span);
+            let unused = !binding.name.starts_with('_') && resolution.references(i).is_empty();
+            match binding.kind {
+                BindingKind::Global | BindingKind::Local if unused => {
+                    self.report(UNUSED_LET, format!("unused variable `{}`", binding.name), span)
+                }
+                BindingKind::Parameter if unused => {
+                    self.report(UNUSED_PARAMETER, format!("unused parameter `{}`", binding.name), span)
+                }
+                _ => {}
+            }
+
+            if let Some(shadowed) = binding.shadows {
+                let message = match resolution.bindings[shadowed]
+                    .declaration
+                    .and_then(|occurrence| resolution.occurrences[occurrence].span)
+                {
+                    Some(earlier) => format!(
+                        "`{}` shadows a binding declared at {}:{}",
+                        binding.name, earlier.line, earlier.column
+                    ),
+                    None => format!("`{}` shadows an earlier binding", binding.name),
+                };
+                self.report(SHADOWED_VARIABLE, message, span);
+            }
+        }
+    }
+}
+
+impl Visitor for Checker {
+    fn visit_block(&mut self, block: &[Statement]) {
+        let mut exit = None;
+        let mut reported = false;
+        for statement in block {
+            if let (Some(keyword), false) = (exit, reported) {
+                let span = self.statement_spans.get(self.next_statement).copied();
+                self.report(
+                    UNREACHABLE_CODE,
+                    format!("unreachable statement after `{}`", keyword),
+                    span,
+                );
+                reported = true;
+            }
+            self.visit_statement(statement);
+            exit = exit.or(match statement {
+                Statement::Return { .. } => Some("return"),
+                Statement::Break(_) => Some("break"),
+                Statement::Continue(_) => Some("continue"),
+                _ => None,
+            });
+        }
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        self.next_statement += 1;
+        walk_statement(self, statement);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Infix {
+                left,
+                operator: Token::Equal | Token::NotEqual,
+                right,
+                location,
+            } => {
+                if let (Expr::Boolean(b), _) | (_, Expr::Boolean(b)) = (left.as_ref(), right.as_ref()) {
+                    let message = format!("comparison with `{}` can be simplified", b);
+                    self.report(BOOL_COMPARISON, message, location.0);
+                }
+            }
+            Expr::If {
+                consequence, location, ..
+            } if consequence.is_empty() => {
+                self.report(EMPTY_IF, "empty `if` branch".to_string(), location.0);
+            }
+            _ => {}
+        }
+        walk_expr(self, expr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(source: &str, config: &LintConfig) -> Vec<String> {
+        lint(source, config).unwrap().iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn reports_unused_bindings() {
+        let source = "let unused = 1;
+let f = fn(a, b, _c) { let d = a; d };
+f(1, 2, 3);";
+
+        assert_eq!(
+            messages(source, &LintConfig::new()),
+            vec![
+                "1:5: warning[unused_let]: unused variable `unused`",
+                "2:15: warning[unused_parameter]: unused parameter `b`",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_shadowed_variables() {
+        let source = "let x = 1;
+let f = fn(x) { x };
+f(x);";
+
+        assert_eq!(
+            messages(source, &LintConfig::new()),
+            vec!["2:12: warning[shadowed_variable]: `x` shadows a binding declared at 1:5"]
+        );
+    }
+
+    #[test]
+    fn reports_unreachable_code_bool_comparisons_and_empty_ifs() {
+        let source = "let f = fn(x) {
+    if (x == true) {
+    } else {
+        return 1;
+        2;
+        3;
+    }
+    while (x) {
+        break;
+        4;
+    }
+};
+f(false);";
+
+        assert_eq!(
+            messages(source, &LintConfig::new()),
+            vec![
+                "2:5: warning[empty_if]: empty `if` branch",
+                "2:11: warning[bool_comparison]: comparison with `true` can be simplified",
+                "5:9: warning[unreachable_code]: unreachable statement after `return`",
+                "10:9: warning[unreachable_code]: unreachable statement after `break`",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_nested_occurrences_at_their_token() {
+        let source = "let x = 1;\nputs(len([x, x == false]) + if x > 0 {} else { 2 });";
+        let spans: Vec<(usize, usize)> = lint(source, &LintConfig::new())
+            .unwrap()
+            .iter()
+            .map(|d| d.span.map(|span| (span.start, span.end)).unwrap())
+            .collect();
+
+        // The `==` of the comparison and the `if` keyword, not the whole `puts(...)` statement.
+        assert_eq!(spans, vec![(26, 28), (39, 41)]);
+        assert_eq!(&source[26..28], "==");
+        assert_eq!(&source[39..41], "if");
+    }
+
+    #[test]
+    fn respects_levels_and_allow_comments() {
+        let source = "// amp-lint: allow(unused_parameter)
+let f = fn(a) { 1 };
+let g = 2;";
+        let mut config = LintConfig::new();
+        config.set(UNUSED_LET, Level::Deny).unwrap();
+        let diagnostics = lint(source, &config).unwrap();
+
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics.iter().all(|d| d.is_error() && d.code == Some(UNUSED_LET)));
+        assert_eq!(
+            config.set("no_such_rule", Level::Warn),
+            Err(AmpError::UnknownLint("no_such_rule".to_string()))
+        );
+    }
+
+    #[test]
+    fn includes_resolution_errors() {
+        let diagnostics = lint("undefined;", &LintConfig::new()).unwrap();
+
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].is_error());
+        assert_eq!(diagnostics[0].code, None);
+    }
+}
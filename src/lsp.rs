@@ -0,0 +1,583 @@
+//! Language Server Protocol support.
+//!
+//! `Server` speaks JSON-RPC with `Content-Length` framing over any reader/writer pair, the
+//! `amp-lsp` binary runs it on stdio. Documents are synchronized in full and analysed on every
+//! change. Positions count UTF-16 code units, the default encoding of the protocol, and are
+//! converted from and to the byte offsets of spans.
+use super::{
+    ast::{Expr, Span, Statement},
+    diagnostic::{Diagnostic, Severity},
+    formatter,
+    lint::{self, LintConfig},
+    parser::parse_with_spans,
+    resolver::{BindingKind, Resolution, Resolver},
+};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+const SYMBOL_FUNCTION: u64 = 12;
+const SYMBOL_VARIABLE: u64 = 13;
+
+type RequestResult = Result<Value, (i64, String)>;
+
+pub struct Server<R, W> {
+    reader: R,
+    writer: W,
+    documents: HashMap<String, String>,
+    shutdown: bool,
+}
+
+impl<R: BufRead, W: Write> Server<R, W> {
+    pub fn new(reader: R, writer: W) -> Server<R, W> {
+        Server {
+            reader,
+            writer,
+            documents: HashMap::new(),
+            shutdown: false,
+        }
+    }
+
+    /// Serves messages until the client sends `exit` or closes the stream. Returns the process
+    /// exit code mandated by the protocol - `0` only if `shutdown` was requested before.
+    pub fn run(&mut self) -> io::Result<i32> {
+        while let Some(body) = self.read_message()? {
+            let message: Value = match serde_json::from_slice(&body) {
+                Ok(message) => message,
+                Err(e) => {
+                    self.send_error(Value::Null, PARSE_ERROR, e.to_string())?;
+                    continue;
+                }
+            };
+            let method = match message["method"].as_str() {
+                Some(method) => method.to_string(),
+                None => {
+                    // Responses to server requests are not used.
+                    if message.get("id").is_some() && message.get("result").is_none() {
+                        self.send_error(message["id"].clone(), INVALID_REQUEST, "missing method".to_string())?;
+                    }
+                    continue;
+                }
+            };
+            let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+            match message.get("id").cloned() {
+                Some(id) => match self.handle_request(&method, &params) {
+                    Ok(result) => self.send(json!({ "jsonrpc": "2.0", "id": id, "result": result }))?,
+                    Err((code, message)) => self.send_error(id, code, message)?,
+                },
+                None if method == "exit" => return Ok(if self.shutdown { 0 } else { 1 }),
+                None => self.handle_notification(&method, &params)?,
+            }
+        }
+
+        Ok(1)
+    }
+
+    fn read_message(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            if self.reader.read_line(&mut header)? == 0 {
+                return Ok(None);
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+
+        let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+        let mut body = vec![0; length];
+        self.reader.read_exact(&mut body)?;
+        Ok(Some(body))
+    }
+
+    fn send(&mut self, message: Value) -> io::Result<()> {
+        let body = message.to_string();
+        write!(self.writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.writer.flush()
+    }
+
+    fn send_error(&mut self, id: Value, code: i64, message: String) -> io::Result<()> {
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": code, "message": message },
+        }))
+    }
+
+    fn handle_notification(&mut self, method: &str, params: &Value) -> io::Result<()> {
+        let uri = match params["textDocument"]["uri"].as_str() {
+            Some(uri) => uri.to_string(),
+            None => return Ok(()),
+        };
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), text.to_string());
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes.and_then(|c| c.last()).and_then(|c| c["text"].as_str()) {
+                    self.documents.insert(uri.clone(), text.to_string());
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+            }
+            _ => return Ok(()),
+        }
+
+        let diagnostics: Vec<Value> = match self.documents.get(&uri) {
+            Some(text) => diagnostics(text)
+                .iter()
+                .map(|diagnostic| to_lsp_diagnostic(text, diagnostic))
+                .collect(),
+            None => Vec::new(),
+        };
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }))
+    }
+
+    fn handle_request(&mut self, method: &str, params: &Value) -> RequestResult {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "hoverProvider": true,
+                    "documentSymbolProvider": true,
+                    "documentFormattingProvider": true,
+                },
+                "serverInfo": { "name": "amp-lsp", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => {
+                let (uri, text) = self.document(params)?;
+                let definition = Analysis::new(text).and_then(|analysis| {
+                    let binding = analysis.binding_at(text, &params["position"])?;
+                    analysis.declaration_span(binding)
+                });
+                Ok(definition.map_or(Value::Null, |span| location(uri, text, span)))
+            }
+            "textDocument/references" => {
+                let (uri, text) = self.document(params)?;
+                let include_declaration = params["context"]["includeDeclaration"].as_bool().unwrap_or(true);
+                let references = Analysis::new(text)
+                    .and_then(|analysis| {
+                        let binding = analysis.binding_at(text, &params["position"])?;
+                        Some(analysis.reference_spans(binding, include_declaration))
+                    })
+                    .unwrap_or_default();
+                Ok(references.into_iter().map(|span| location(uri, text, span)).collect())
+            }
+            "textDocument/hover" => {
+                let (_, text) = self.document(params)?;
+                let hover = Analysis::new(text).and_then(|analysis| analysis.hover(text, &params["position"]));
+                Ok(hover.unwrap_or(Value::Null))
+            }
+            "textDocument/documentSymbol" => {
+                let (_, text) = self.document(params)?;
+                Ok(Analysis::new(text).map_or_else(|| json!([]), |analysis| analysis.symbols(text)))
+            }
+            "textDocument/formatting" => {
+                let (_, text) = self.document(params)?;
+                Ok(match formatter::format(text) {
+                    Ok(formatted) if formatted != text => json!([{
+                        "range": { "start": position(0, 0), "end": position_at(text, text.len()) },
+                        "newText": formatted,
+                    }]),
+                    Ok(_) => json!([]),
+                    Err(_) => Value::Null,
+                })
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method '{}'", method))),
+        }
+    }
+
+    fn document<'a>(&'a self, params: &'a Value) -> Result<(&'a str, &'a str), (i64, String)> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .ok_or_else(|| (INVALID_PARAMS, "missing textDocument.uri".to_string()))?;
+        match self.documents.get(uri) {
+            Some(text) => Ok((uri, text)),
+            None => Err((INVALID_PARAMS, format!("unknown document '{}'", uri))),
+        }
+    }
+}
+
+/// Parse error, resolution errors and lints of a document.
+fn diagnostics(text: &str) -> Vec<Diagnostic> {
+    match parse_with_spans(text) {
+        Err(e) => vec![Diagnostic::error(e.error.to_string(), Some(e.span))],
+        Ok(_) => lint::lint(text, &LintConfig::new()).unwrap_or_default(),
+    }
+}
+
+struct Analysis {
+    program: Vec<Statement>,
+    resolution: Resolution,
+}
+
+impl Analysis {
+    fn new(text: &str) -> Option<Analysis> {
+        let (mut program, _) = parse_with_spans(text).ok()?;
+        let resolution = Resolver::new().resolve(&mut program);
+        Some(Analysis { program, resolution })
+    }
+
+    fn binding_at(&self, text: &str, position: &Value) -> Option<usize> {
+        let offset = offset_at(text, position)?;
+        self.resolution.occurrences.iter().find_map(|occurrence| {
+            let span = occurrence.span?;
+            if span.start <= offset && offset <= span.end {
+                occurrence.binding
+            } else {
+                None
+            }
+        })
+    }
+
+    fn declaration_span(&self, binding: usize) -> Option<Span> {
+        let occurrence = self.resolution.bindings[binding].declaration?;
+        self.resolution.occurrences[occurrence].span
+    }
+
+    fn reference_spans(&self, binding: usize, include_declaration: bool) -> Vec<Span> {
+        let mut occurrences = self.resolution.references(binding);
+        if include_declaration {
+            occurrences.extend(self.resolution.bindings[binding].declaration);
+            occurrences.sort_unstable();
+        }
+        occurrences
+            .into_iter()
+            .filter_map(|occurrence| self.resolution.occurrences[occurrence].span)
+            .collect()
+    }
+
+    fn hover(&self, text: &str, position: &Value) -> Option<Value> {
+        let binding = self.binding_at(text, position)?;
+        let binding = &self.resolution.bindings[binding];
+        let kind = match binding.kind {
+            BindingKind::Global => "global",
+            BindingKind::Local => "local",
+            BindingKind::Parameter => "parameter",
+            BindingKind::External => "external",
+            BindingKind::Builtin => "builtin",
+        };
+        let mut value = format!("({}) `{}`", kind, binding.name);
+        if let Some(span) = binding
+            .declaration
+            .and_then(|occurrence| self.resolution.occurrences[occurrence].span)
+        {
+            let line = text.lines().nth(span.line - 1).unwrap_or_default().trim();
+            value.push_str(&format!("\n```amp\n{}\n```", line));
+        }
+        Some(json!({ "contents": { "kind": "markdown", "value": value } }))
+    }
+
+    /// Top level `let` bindings.
+    fn symbols(&self, text: &str) -> Value {
+        let globals = self
+            .resolution
+            .bindings
+            .iter()
+            .filter(|binding| binding.kind == BindingKind::Global);
+        let lets = self.program.iter().filter_map(|statement| match statement {
+            Statement::Let { value, .. } => Some(value),
+            _ => None,
+        });
+
+        globals
+            .zip(lets)
+            .filter_map(|(binding, value)| {
+                let span = self.resolution.occurrences[binding.declaration?].span?;
+                let kind = match value.as_ref() {
+                    Expr::Function { .. } => SYMBOL_FUNCTION,
+                    _ => SYMBOL_VARIABLE,
+                };
+                Some(json!({
+                    "name": binding.name,
+                    "kind": kind,
+                    "range": range(text, span),
+                    "selectionRange": range(text, span),
+                }))
+            })
+            .collect()
+    }
+}
+
+fn position(line: usize, character: usize) -> Value {
+    json!({ "line": line, "character": character })
+}
+
+/// Position of the byte `offset` in `text`.
+fn position_at(text: &str, offset: usize) -> Value {
+    let before = text.get(..offset).unwrap_or(text);
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    position(
+        before.matches('\n').count(),
+        before[line_start..].encode_utf16().count(),
+    )
+}
+
+/// Byte offset of `position` in `text`. A position past the end of its line is at the end of it.
+fn offset_at(text: &str, position: &Value) -> Option<usize> {
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+    let line_start = match line {
+        0 => 0,
+        _ => text.match_indices('\n').nth(line - 1)?.0 + 1,
+    };
+    let mut units = 0;
+    for (i, ch) in text[line_start..].char_indices() {
+        if units >= character || ch == '\n' {
+            return Some(line_start + i);
+        }
+        units += ch.len_utf16();
+    }
+    Some(text.len())
+}
+
+fn range(text: &str, span: Span) -> Value {
+    json!({ "start": position_at(text, span.start), "end": position_at(text, span.end) })
+}
+
+fn location(uri: &str, text: &str, span: Span) -> Value {
+    json!({ "uri": uri, "range": range(text, span) })
+}
+
+fn to_lsp_diagnostic(text: &str, diagnostic: &Diagnostic) -> Value {
+    let span = diagnostic.span.unwrap_or_default();
+    let mut value = json!({
+        "range": range(text, span),
+        "severity": match diagnostic.severity {
+            Severity::Error => 1,
+            Severity::Warning => 2,
+        },
+        "source": "amp",
+        "message": diagnostic.message,
+    });
+    if let Some(code) = diagnostic.code {
+        value["code"] = json!(code);
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const URI: &str = "file:///test.amp";
+    const SOURCE: &str = "let add = fn(x, y) {
+    x + y
+};
+let five = 5;
+add(five, five);
+";
+
+    /// Frames the scripted messages, runs a server over them and returns the exit code and all
+    /// messages the server sent back.
+    fn run_script(messages: &[Value]) -> (i32, Vec<Value>) {
+        let mut input = Vec::new();
+        for message in messages {
+            let body = message.to_string();
+            input.extend(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).into_bytes());
+        }
+        let mut output = Vec::new();
+        let code = Server::new(Cursor::new(input), &mut output).run().unwrap();
+
+        let mut responses = Vec::new();
+        let mut reader = Cursor::new(output);
+        let mut client = Server::new(&mut reader, Vec::new());
+        while let Some(body) = client.read_message().unwrap() {
+            responses.push(serde_json::from_slice(&body).unwrap());
+        }
+        (code, responses)
+    }
+
+    fn request(id: u64, method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+    }
+
+    fn notification(method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "method": method, "params": params })
+    }
+
+    fn open(text: &str) -> Value {
+        notification(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": URI, "languageId": "amp", "version": 1, "text": text } }),
+        )
+    }
+
+    fn at(line: usize, character: usize) -> Value {
+        json!({ "textDocument": { "uri": URI }, "position": position(line, character) })
+    }
+
+    fn response(responses: &[Value], id: u64) -> &Value {
+        &responses.iter().find(|r| r["id"] == json!(id)).unwrap()["result"]
+    }
+
+    #[test]
+    fn initializes_and_shuts_down() {
+        let (code, responses) = run_script(&[
+            request(1, "initialize", json!({ "capabilities": {} })),
+            notification("initialized", json!({})),
+            request(2, "shutdown", Value::Null),
+            notification("exit", Value::Null),
+        ]);
+
+        assert_eq!(code, 0);
+        assert_eq!(responses.len(), 2);
+        assert_eq!(
+            responses[0]["result"]["capabilities"]["definitionProvider"],
+            json!(true)
+        );
+        assert_eq!(responses[1]["result"], Value::Null);
+    }
+
+    #[test]
+    fn exits_with_error_without_shutdown() {
+        let (code, _) = run_script(&[notification("exit", Value::Null)]);
+        assert_eq!(code, 1);
+    }
+
+    #[test]
+    fn publishes_diagnostics() {
+        let (_, responses) = run_script(&[
+            open("let x = ;"),
+            notification(
+                "textDocument/didChange",
+                json!({ "textDocument": { "uri": URI, "version": 2 }, "contentChanges": [{ "text": "y;" }] }),
+            ),
+        ]);
+
+        assert_eq!(responses.len(), 2);
+        let parse_error = &responses[0]["params"]["diagnostics"][0];
+        assert_eq!(parse_error["range"]["start"], position(0, 8));
+        assert_eq!(parse_error["severity"], json!(1));
+        let undefined = &responses[1]["params"]["diagnostics"][0];
+        assert_eq!(undefined["message"], json!("undefined variable `y`"));
+    }
+
+    #[test]
+    fn finds_definitions_references_and_hovers() {
+        let (_, responses) = run_script(&[
+            open(SOURCE),
+            request(1, "textDocument/definition", at(1, 4)),
+            request(
+                2,
+                "textDocument/references",
+                json!({
+                    "textDocument": { "uri": URI },
+                    "position": position(3, 5),
+                    "context": { "includeDeclaration": true },
+                }),
+            ),
+            request(3, "textDocument/hover", at(4, 1)),
+            request(4, "textDocument/definition", at(2, 0)),
+        ]);
+
+        assert_eq!(
+            response(&responses, 1),
+            &json!({ "uri": URI, "range": { "start": position(0, 13), "end": position(0, 14) } })
+        );
+        let references: Vec<&Value> = response(&responses, 2)
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|location| &location["range"]["start"])
+            .collect();
+        assert_eq!(references, vec![&position(3, 4), &position(4, 4), &position(4, 10)]);
+        assert_eq!(
+            response(&responses, 3)["contents"]["value"],
+            json!("(global) `add`\n```amp\nlet add = fn(x, y) {\n```")
+        );
+        assert_eq!(response(&responses, 4), &Value::Null);
+    }
+
+    #[test]
+    fn counts_characters_in_utf16_code_units() {
+        // `😀` is two UTF-16 code units and four bytes, `é` is one code unit and two bytes.
+        let (_, responses) = run_script(&[
+            open("let s = \"😀é\"; s;\n\"é\" + y;"),
+            request(1, "textDocument/references", at(0, 15)),
+            request(2, "textDocument/definition", at(0, 16)),
+        ]);
+
+        let undefined = &responses[0]["params"]["diagnostics"][0];
+        assert_eq!(undefined["message"], json!("undefined variable `y`"));
+        assert_eq!(
+            undefined["range"],
+            json!({ "start": position(1, 6), "end": position(1, 7) })
+        );
+        let references: Vec<&Value> = response(&responses, 1)
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|location| &location["range"])
+            .collect();
+        assert_eq!(
+            references,
+            vec![
+                &json!({ "start": position(0, 4), "end": position(0, 5) }),
+                &json!({ "start": position(0, 15), "end": position(0, 16) }),
+            ]
+        );
+        assert_eq!(response(&responses, 2)["range"]["start"], position(0, 4));
+    }
+
+    #[test]
+    fn lists_symbols_and_formats() {
+        let (_, responses) = run_script(&[
+            open("let f=fn(a){a};let v=f(1);"),
+            request(
+                1,
+                "textDocument/documentSymbol",
+                json!({ "textDocument": { "uri": URI } }),
+            ),
+            request(
+                2,
+                "textDocument/formatting",
+                json!({ "textDocument": { "uri": URI }, "options": {} }),
+            ),
+            request(3, "textDocument/unknown", json!({})),
+        ]);
+
+        let symbols = response(&responses, 1).as_array().unwrap();
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols[0]["name"], json!("f"));
+        assert_eq!(symbols[0]["kind"], json!(SYMBOL_FUNCTION));
+        assert_eq!(symbols[1]["kind"], json!(SYMBOL_VARIABLE));
+
+        let edits = response(&responses, 2).as_array().unwrap();
+        assert_eq!(
+            edits[0]["newText"],
+            json!("let f = fn(a) {\n    a\n};\nlet v = f(1);\n")
+        );
+        assert_eq!(edits[0]["range"]["end"], position(0, 26));
+
+        let unknown = responses.iter().find(|r| r["id"] == json!(3)).unwrap();
+        assert_eq!(unknown["error"]["code"], json!(METHOD_NOT_FOUND));
+    }
+}
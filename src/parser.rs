@@ -1,14 +1,45 @@
 use super::{
-    ast::{EKeyword, Expr, Precedence, Statement, Token},
+    ast::{EKeyword, Expr, Ident, Location, Precedence, Span, Statement, Token},
     lexer::Lexer,
     AmpError,
 };
 use log::debug;
 
-pub(crate) fn parse_program(src: &str) -> Result<Vec<Statement>, AmpError> {
+/// Nesting of expressions and blocks allowed by `parse_program`. Deeper programs are rejected
+/// before the recursive descent can overflow the native stack.
+pub const DEFAULT_MAX_NESTING: usize = 128;
+
+pub fn parse_program(src: &str) -> Result<Vec<Statement>, AmpError> {
+    parse_program_with_max_nesting(src, DEFAULT_MAX_NESTING)
+}
+
+/// Parses `src`, failing with `AmpError::NestingTooDeep` when expressions nest deeper than
+/// `max_nesting`.
+pub fn parse_program_with_max_nesting(src: &str, max_nesting: usize) -> Result<Vec<Statement>, AmpError> {
     let mut p = Parser::new(src);
-    let statements = p.parse();
-    statements
+    p.max_nesting = max_nesting;
+    Ok(p.parse_to_end()?.0)
+}
+
+/// A parse error together with the location of the token that caused it.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ParseError {
+    pub(crate) error: AmpError,
+    pub(crate) span: Span,
+}
+
+impl From<ParseError> for AmpError {
+    fn from(e: ParseError) -> AmpError {
+        e.error
+    }
+}
+
+/// Parses `src` and additionally returns the span of the first token of every statement.
+///
+/// Spans are listed in the order the statements start in the source, which is the order of a
+/// pre-order walk visiting nested blocks in source order.
+pub(crate) fn parse_with_spans(src: &str) -> Result<(Vec<Statement>, Vec<Span>), ParseError> {
+    Parser::new(src).parse_to_end()
 }
 
 macro_rules! function_name {
@@ -34,6 +65,13 @@ struct Parser<'s> {
     lexer: Lexer<'s>,
     current: Token,
     peek: Token,
+    current_span: Span,
+    peek_span: Span,
+    statement_spans: Vec<Span>,
+    /// Set when an error is caused by the peek token rather than the current one.
+    error_span: Option<Span>,
+    nesting: usize,
+    max_nesting: usize,
 }
 impl<'s> Parser<'s> {
     fn new(source: &'s str) -> Parser<'s> {
@@ -41,8 +79,32 @@ impl<'s> Parser<'s> {
             lexer: Lexer::new(source),
             current: Token::Null,
             peek: Token::Null,
+            current_span: Span::default(),
+            peek_span: Span::default(),
+            statement_spans: Vec::new(),
+            error_span: None,
+            nesting: 0,
+            max_nesting: DEFAULT_MAX_NESTING,
         }
     }
+
+    /// Parses the whole source, which must not have anything left after the last statement.
+    fn parse_to_end(mut self) -> Result<(Vec<Statement>, Vec<Span>), ParseError> {
+        let result = self.parse().and_then(|statements| {
+            if self.current != Token::EOF {
+                return Err(AmpError::UnexpectedToken(self.current.clone()));
+            }
+            Ok(statements)
+        });
+        match result {
+            Ok(statements) => Ok((statements, self.statement_spans)),
+            Err(error) => Err(ParseError {
+                error,
+                span: self.error_span.unwrap_or(self.current_span),
+            }),
+        }
+    }
+
     fn expect(&mut self, first: &Token, second: &Token) -> Result<(), AmpError> {
         if first != second {
             return Err(AmpError::InvalidToken(first.clone(), second.clone()));
@@ -55,67 +117,118 @@ impl<'s> Parser<'s> {
         format!("current - '{:?}', peek - '{:?}'", self.current, self.peek)
     }
 
-    #[allow(dead_code)]
     fn expect_current(&mut self, token: &Token) -> Result<(), AmpError> {
-        self.expect(&self.current.clone(), &token)
+        self.expect(&self.current.clone(), token)
     }
 
     fn expect_peek(&mut self, token: &Token) -> Result<(), AmpError> {
         ldebug!(format!("got '{:?}', expecting peek '{:?}'", &self.peek, &token));
-        self.expect(&self.peek.clone(), &token)
+        let result = self.expect(&self.peek.clone(), token);
+        if result.is_err() {
+            self.error_span = Some(self.peek_span);
+        }
+        result
     }
 
     fn next(&mut self) {
         std::mem::swap(&mut self.peek, &mut self.current);
-        self.peek = self.lexer.next_token();
+        self.current_span = self.peek_span;
+        self.peek = loop {
+            match self.lexer.next_token() {
+                Token::Comment(_) => continue,
+                token => break token,
+            }
+        };
+        self.peek_span = self.lexer.span();
         ldebug!(format!("after `{}`", self.dbg()));
     }
 
+    /// Counts one more level of nesting for an expression or a block. Callers decrement
+    /// `nesting` again when they succeed, an error ends the parse anyway.
+    fn enter(&mut self) -> Result<(), AmpError> {
+        self.nesting += 1;
+        if self.nesting > self.max_nesting {
+            return Err(AmpError::NestingTooDeep {
+                limit: self.max_nesting,
+                line: self.current_span.line,
+                column: self.current_span.column,
+            });
+        }
+        Ok(())
+    }
+
     fn parse(&mut self) -> Result<Vec<Statement>, AmpError> {
-        let mut stmts = Vec::new();
+        self.parse_rest(Vec::new())
+    }
+
+    /// Parses statements up to the end of the block or the program, after the ones in `stmts`.
+    ///
+    /// An expression statement that ends a block with a semicolon is followed by an empty
+    /// statement, so that it is not the value of the block. The value of a program is the value
+    /// of its last statement either way.
+    fn parse_rest(&mut self, mut stmts: Vec<Statement>) -> Result<Vec<Statement>, AmpError> {
+        self.enter()?;
+        let mut terminator = None;
         loop {
             ldebug!(format!("begin `{}`", self.dbg()));
             let statement = match self.current.clone() {
                 Token::Keyword(EKeyword::Let) => self.parse_let_statement()?,
                 Token::Keyword(EKeyword::Return) => self.parse_return_statement()?,
-                Token::EOF => {
+                Token::Keyword(EKeyword::While) => self.parse_while_statement()?,
+                Token::Keyword(EKeyword::For) => self.parse_for_statement()?,
+                Token::Keyword(EKeyword::Break) => Statement::Break(self.parse_jump_statement()),
+                Token::Keyword(EKeyword::Continue) => Statement::Continue(self.parse_jump_statement()),
+                Token::EOF => break,
+                Token::RightCurlyBrace => {
+                    if let Some(span) = terminator {
+                        self.statement_spans.push(span);
+                        stmts.push(Statement::Empty);
+                    }
                     break;
                 }
-                Token::Keyword(EKeyword::Else) | Token::RightCurlyBrace => {
-                    break;
+                Token::SemiColon if terminator.is_none() && matches!(stmts.last(), Some(Statement::Expression(_))) => {
+                    terminator = Some(self.current_span);
+                    self.next();
+                    continue;
                 }
                 Token::SemiColon | Token::Null => {
                     self.next();
                     continue;
                 }
-                _ => Statement::Expression(Box::new(self.parse_expr(Precedence::Lowest)?)),
+                _ => {
+                    self.statement_spans.push(self.current_span);
+                    Statement::Expression(Box::new(self.parse_expr(Precedence::Lowest)?))
+                }
             };
 
             stmts.push(statement);
-
-            if self.lexer.is_last() {
-                break;
-            }
-
+            terminator = None;
             self.next();
         }
 
+        self.nesting -= 1;
         Ok(stmts)
     }
 
-    #[allow(unused_assignments)]
-    fn parse_let_statement(&mut self) -> Result<Statement, AmpError> {
-        ldebug!(format!("begin `{}`", self.dbg()));
-        self.next();
-        let mut ident = String::new();
+    fn parse_ident(&mut self) -> Result<Ident, AmpError> {
         if let Token::Identifier(name) = self.current.clone() {
-            ident = name;
+            Ok(Ident {
+                name,
+                location: self.current_span.into(),
+            })
         } else {
-            return Err(AmpError::InvalidToken(
+            Err(AmpError::InvalidToken(
                 self.current.clone(),
                 Token::Identifier("".to_string()),
-            ));
+            ))
         }
+    }
+
+    fn parse_let_statement(&mut self) -> Result<Statement, AmpError> {
+        ldebug!(format!("begin `{}`", self.dbg()));
+        self.statement_spans.push(self.current_span);
+        self.next();
+        let ident = self.parse_ident()?;
         self.expect_peek(&Token::Assign)?;
         self.next();
         self.next();
@@ -126,13 +239,14 @@ impl<'s> Parser<'s> {
         ldebug!(format!("end `{}`", self.dbg()));
 
         Ok(Statement::Let {
-            ident: Token::Identifier(ident),
+            ident,
             value: Box::new(expr),
         })
     }
 
     fn parse_return_statement(&mut self) -> Result<Statement, AmpError> {
         ldebug!(format!("begin `{}`", self.dbg()));
+        self.statement_spans.push(self.current_span);
         self.next();
         let expr = self.parse_expr(Precedence::Lowest)?;
         self.expect_peek(&Token::SemiColon)?;
@@ -141,41 +255,232 @@ impl<'s> Parser<'s> {
         Ok(Statement::Return { value: Box::new(expr) })
     }
 
-    fn parse_if_expr(&mut self) -> Result<Expr, AmpError> {
+    fn parse_while_statement(&mut self) -> Result<Statement, AmpError> {
         ldebug!(format!("begin `{}`", self.dbg()));
+        self.statement_spans.push(self.current_span);
+        let condition = self.parse_condition()?;
+        let body = self.parse_curly_block()?;
+        Ok(Statement::While {
+            condition: Box::new(condition),
+            body,
+        })
+    }
+
+    fn parse_for_statement(&mut self) -> Result<Statement, AmpError> {
+        ldebug!(format!("begin `{}`", self.dbg()));
+        self.statement_spans.push(self.current_span);
         self.expect_peek(&Token::LeftParenthesis)?;
         self.next();
         self.next();
-        ldebug!(format!("parsing condition `{}`", self.dbg()));
-        let condition = self.parse_expr(Precedence::Lowest);
+        let variable = self.parse_ident()?;
+        self.expect_peek(&Token::Keyword(EKeyword::In))?;
+        self.next();
+        self.next();
+        let iterable = self.parse_expr(Precedence::Lowest)?;
         self.expect_peek(&Token::RightParenthesis)?;
         self.next();
+        let body = self.parse_curly_block()?;
+        Ok(Statement::For {
+            variable,
+            iterable: Box::new(iterable),
+            body,
+        })
+    }
+
+    /// Parses `break` or `continue` and returns the location of the keyword, the semicolon after
+    /// them is optional.
+    fn parse_jump_statement(&mut self) -> Location {
+        let span = self.current_span;
+        self.statement_spans.push(span);
+        if self.peek == Token::SemiColon {
+            self.next();
+        }
+        span.into()
+    }
+
+    fn parse_if_expr(&mut self) -> Result<Expr, AmpError> {
+        ldebug!(format!("begin `{}`", self.dbg()));
+        let location = self.current_span.into();
+        let condition = self.parse_condition()?;
         ldebug!(format!("parsing consequence `{}`", self.dbg()));
         let consequence = self.parse_curly_block()?;
         ldebug!(format!("parsing alternative `{}`", self.dbg()));
-        let alternative = if self.current == Token::Keyword(EKeyword::Else) {
-            self.parse_curly_block()?
+        let alternative = if self.peek == Token::Keyword(EKeyword::Else) {
+            self.next();
+            if self.peek == Token::Keyword(EKeyword::If) {
+                // `else if` is an `else` block that holds just the next `if`. Every link of the
+                // chain is a level of nesting.
+                self.next();
+                self.statement_spans.push(self.current_span);
+                self.enter()?;
+                let nested = self.parse_if_expr()?;
+                self.nesting -= 1;
+                vec![Statement::Expression(Box::new(nested))]
+            } else {
+                self.parse_curly_block()?
+            }
         } else {
             Vec::new()
         };
 
         Ok(Expr::If {
-            condition: Box::new(condition?),
+            condition: Box::new(condition),
             consequence,
             alternative,
+            location,
         })
     }
 
+    /// Parses the condition of an `if` or a `while`, which starts at the peek token. Parentheses
+    /// around it are optional: `(x)` is just a grouped expression.
+    fn parse_condition(&mut self) -> Result<Expr, AmpError> {
+        ldebug!(format!("parsing condition `{}`", self.dbg()));
+        self.next();
+        self.parse_expr(Precedence::Lowest)
+    }
+
+    /// Parses a `{ ... }` block that starts at the peek token. Leaves the closing brace as the
+    /// current token.
     fn parse_curly_block(&mut self) -> Result<Vec<Statement>, AmpError> {
         ldebug!(format!("begin `{}`", self.dbg()));
         self.expect_peek(&Token::LeftCurlyBrace)?;
         self.next();
         self.next();
-        let out = self.parse();
+        let out = self.parse()?;
         ldebug!(format!("after parse `{}`", self.dbg()));
-        self.next();
+        self.expect_current(&Token::RightCurlyBrace)?;
         ldebug!(format!("[{}] out - '{:?}'", function_name!(), &out));
-        Ok(out?)
+        Ok(out)
+    }
+
+    fn parse_function_expr(&mut self) -> Result<Expr, AmpError> {
+        ldebug!(format!("begin `{}`", self.dbg()));
+        self.expect_peek(&Token::LeftParenthesis)?;
+        self.next();
+        let mut parameters = Vec::new();
+        if self.peek == Token::RightParenthesis {
+            self.next();
+        } else {
+            loop {
+                self.next();
+                parameters.push(self.parse_ident()?);
+                self.next();
+                match &self.current {
+                    Token::Comma => continue,
+                    Token::RightParenthesis => break,
+                    t => return Err(AmpError::InvalidToken(t.clone(), Token::RightParenthesis)),
+                }
+            }
+        }
+        let body = self.parse_curly_block()?;
+
+        Ok(Expr::Function { parameters, body })
+    }
+
+    /// Parses comma separated expressions up to `end`, starting with the opening token as the
+    /// current token. Leaves `end` as the current token.
+    fn parse_expr_list(&mut self, end: Token) -> Result<Vec<Expr>, AmpError> {
+        let mut list = Vec::new();
+        if self.peek == end {
+            self.next();
+            return Ok(list);
+        }
+        loop {
+            self.next();
+            list.push(self.parse_expr(Precedence::Lowest)?);
+            self.next();
+            match &self.current {
+                Token::Comma => continue,
+                t if *t == end => break,
+                t => return Err(AmpError::InvalidToken(t.clone(), end)),
+            }
+        }
+        Ok(list)
+    }
+
+    fn parse_call_expr(&mut self, function: Expr) -> Result<Expr, AmpError> {
+        ldebug!(format!("begin `{}`", self.dbg()));
+        let arguments = self.parse_expr_list(Token::RightParenthesis)?;
+        Ok(Expr::Call {
+            function: Box::new(function),
+            arguments,
+        })
+    }
+
+    fn parse_index_expr(&mut self, left: Expr) -> Result<Expr, AmpError> {
+        ldebug!(format!("begin `{}`", self.dbg()));
+        self.next();
+        let index = self.parse_expr(Precedence::Lowest)?;
+        self.expect_peek(&Token::RightSquareBrace)?;
+        self.next();
+        Ok(Expr::Index {
+            left: Box::new(left),
+            index: Box::new(index),
+        })
+    }
+
+    /// Parses `{` in expression position. It starts a hash literal when it is immediately closed or
+    /// when the first expression inside is followed by `:`, otherwise it starts a block.
+    fn parse_brace_expr(&mut self) -> Result<Expr, AmpError> {
+        ldebug!(format!("begin `{}`", self.dbg()));
+        if self.peek == Token::RightCurlyBrace {
+            self.next();
+            return Ok(Expr::Hash(Vec::new()));
+        }
+        self.next();
+        if let Token::Keyword(
+            EKeyword::Let | EKeyword::Return | EKeyword::While | EKeyword::For | EKeyword::Break | EKeyword::Continue,
+        )
+        | Token::SemiColon = self.current
+        {
+            return self.parse_block_expr(Vec::new());
+        }
+
+        // The first expression is a statement of the block unless it turns out to be a key.
+        let span_index = self.statement_spans.len();
+        self.statement_spans.push(self.current_span);
+        let mut key = self.parse_expr(Precedence::Lowest)?;
+        if self.peek != Token::Colon {
+            self.next();
+            return self.parse_block_expr(vec![Statement::Expression(Box::new(key))]);
+        }
+        self.statement_spans.remove(span_index);
+
+        let mut pairs = Vec::new();
+        loop {
+            self.expect_peek(&Token::Colon)?;
+            self.next();
+            self.next();
+            let value = self.parse_expr(Precedence::Lowest)?;
+            pairs.push((key, value));
+            self.next();
+            match &self.current {
+                Token::Comma => {
+                    self.next();
+                    key = self.parse_expr(Precedence::Lowest)?;
+                }
+                Token::RightCurlyBrace => break,
+                t => return Err(AmpError::InvalidToken(t.clone(), Token::RightCurlyBrace)),
+            }
+        }
+        Ok(Expr::Hash(pairs))
+    }
+
+    /// Parses the rest of a block expression after the statements in `stmts`, starting with the
+    /// current token. Leaves the closing brace as the current token.
+    fn parse_block_expr(&mut self, stmts: Vec<Statement>) -> Result<Expr, AmpError> {
+        let body = self.parse_rest(stmts)?;
+        self.expect_current(&Token::RightCurlyBrace)?;
+        Ok(Expr::Block(body))
+    }
+
+    fn parse_grouped_expr(&mut self) -> Result<Expr, AmpError> {
+        ldebug!(format!("begin `{}`", self.dbg()));
+        self.next();
+        let expr = self.parse_expr(Precedence::Lowest)?;
+        self.expect_peek(&Token::RightParenthesis)?;
+        self.next();
+        Ok(expr)
     }
 
     fn parse_prefix_expr(&mut self, prefix: Token) -> Result<Expr, AmpError> {
@@ -189,91 +494,98 @@ impl<'s> Parser<'s> {
         })
     }
 
+    fn parse_infix_expr(&mut self, left: Expr, operator: Token) -> Result<Expr, AmpError> {
+        ldebug!(format!("begin `{}`", self.dbg()));
+        let location = self.current_span.into();
+        // The right operand of `**` may contain another `**`, which makes it right associative.
+        let precedence = match operator {
+            Token::Power => Precedence::Prefix,
+            _ => operator.precedence(),
+        };
+        self.next();
+        let right = self.parse_expr(precedence)?;
+        Ok(Expr::Infix {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+            location,
+        })
+    }
+
+    /// Parses the value of an assignment. The value is parsed with the lowest precedence, which
+    /// makes assignment right associative: `a = b = 1` assigns `b` first.
+    fn parse_assign_expr(&mut self, target: Expr, operator: Token) -> Result<Expr, AmpError> {
+        ldebug!(format!("begin `{}`", self.dbg()));
+        if !is_assignable(&target) {
+            return Err(AmpError::InvalidAssignmentTarget);
+        }
+        self.next();
+        let value = self.parse_expr(Precedence::Lowest)?;
+        Ok(Expr::Assign {
+            target: Box::new(target),
+            operator,
+            value: Box::new(value),
+        })
+    }
+
     fn parse_expr(&mut self, precedence: Precedence) -> Result<Expr, AmpError> {
         ldebug!(format!("begin `{}`", self.dbg()));
-        let expr = match self.current.clone() {
+        self.enter()?;
+        let mut expr = match self.current.clone() {
             Token::Integer(n) => Expr::Const(n),
             Token::Keyword(EKeyword::True) => Expr::Boolean(true),
             Token::Keyword(EKeyword::False) => Expr::Boolean(false),
-            Token::Identifier(s) => Expr::Ident(s),
-            t @ Token::Bang | t @ Token::Minus => self.parse_prefix_expr(t)?,
-            Token::LeftCurlyBrace => {
-                self.next();
-                self.parse_expr(Precedence::Lowest)?
-            }
+            Token::Identifier(_) => Expr::Ident(self.parse_ident()?),
+            t @ Token::Bang | t @ Token::Minus | t @ Token::Tilde => self.parse_prefix_expr(t)?,
+            Token::String(s) => Expr::String(s),
+            Token::LeftCurlyBrace => self.parse_brace_expr()?,
+            Token::LeftParenthesis => self.parse_grouped_expr()?,
+            Token::LeftSquareBrace => Expr::Array(self.parse_expr_list(Token::RightSquareBrace)?),
             Token::Keyword(EKeyword::If) => self.parse_if_expr()?,
-            t => panic!("Unknown token {:?}", t),
+            Token::Keyword(EKeyword::Function) => self.parse_function_expr()?,
+            t => return Err(AmpError::UnexpectedToken(t)),
         };
+
+        // Every operator makes the tree one level deeper, so long chains like `1 + 1 + ...` or
+        // `a[0][0]...` count against the limit too.
+        let mut levels = 1;
+        while self.peek != Token::SemiColon && precedence < self.peek.precedence() {
+            self.next();
+            self.enter()?;
+            levels += 1;
+            expr = match self.current.clone() {
+                Token::LeftParenthesis => self.parse_call_expr(expr)?,
+                Token::LeftSquareBrace => self.parse_index_expr(expr)?,
+                t if t.precedence() == Precedence::Assign => self.parse_assign_expr(expr, t)?,
+                t => self.parse_infix_expr(expr, t)?,
+            };
+        }
         ldebug!(format!("[{}] out - '{:?}'", function_name!(), &expr));
         ldebug!(format!("end `{}`", self.dbg()));
+        self.nesting -= levels;
         Ok(expr)
     }
 }
-pub fn parses_if_else() {
-    let code = "if (x) {
-    return 15;
-}
-    else {
-    return 30;
-    }";
-    let expected = vec![Statement::Expression(Box::new(Expr::If {
-        condition: Box::new(Expr::Ident("x".to_string())),
-        consequence: vec![Statement::Return {
-            value: Box::new(Expr::Const(15)),
-        }],
-        alternative: vec![Statement::Return {
-            value: Box::new(Expr::Const(30)),
-        }],
-    }))];
-    let mut parser = Parser::new(code);
-
-    assert_eq!(parser.parse(), Ok(expected));
-}
-pub fn parses_let_statement() {
-    let code = "let var = 5;
-let is_true = true;
-let is_false = false;";
-    let expected = vec![
-        Statement::Let {
-            ident: Token::Identifier("var".to_string()),
-            value: Box::new(Expr::Const(5)),
-        },
-        Statement::Let {
-            ident: Token::Identifier("is_true".to_string()),
-            value: Box::new(Expr::Boolean(true)),
-        },
-        Statement::Let {
-            ident: Token::Identifier("is_false".to_string()),
-            value: Box::new(Expr::Boolean(false)),
-        },
-    ];
-    let mut parser = Parser::new(code);
-
-    assert_eq!(parser.parse(), Ok(expected));
-}
-pub fn parses_prefix_expression() {
-    let code = "
-            -1000;
-            !true;
-            ";
-    let expected = vec![
-        Statement::Expression(Box::new(Expr::Prefix {
-            prefix: Token::Minus,
-            value: Box::new(Expr::Const(1000)),
-        })),
-        Statement::Expression(Box::new(Expr::Prefix {
-            prefix: Token::Bang,
-            value: Box::new(Expr::Boolean(true)),
-        })),
-    ];
-    let mut parser = Parser::new(code);
-
-    assert_eq!(parser.parse(), Ok(expected));
+
+/// Whether `expr` can be assigned to: an identifier, possibly indexed.
+fn is_assignable(expr: &Expr) -> bool {
+    match expr {
+        Expr::Ident(_) => true,
+        Expr::Index { left, .. } => is_assignable(left),
+        _ => false,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::visit::strip_locations;
+
+    /// Parses `code` without source locations, to compare it with trees built by hand.
+    fn parse_shape(code: &str) -> Result<Vec<Statement>, AmpError> {
+        parse_program(code).map(strip_locations)
+    }
+
     #[test]
     fn parses_let_statement() {
         let code = "let var = 5;
@@ -281,21 +593,19 @@ let is_true = true;
 let is_false = false;";
         let expected = vec![
             Statement::Let {
-                ident: Token::Identifier("var".to_string()),
+                ident: Ident::new("var"),
                 value: Box::new(Expr::Const(5)),
             },
             Statement::Let {
-                ident: Token::Identifier("is_true".to_string()),
+                ident: Ident::new("is_true"),
                 value: Box::new(Expr::Boolean(true)),
             },
             Statement::Let {
-                ident: Token::Identifier("is_false".to_string()),
+                ident: Ident::new("is_false"),
                 value: Box::new(Expr::Boolean(false)),
             },
         ];
-        let mut parser = Parser::new(code);
-
-        assert_eq!(parser.parse(), Ok(expected));
+        assert_eq!(parse_shape(code), Ok(expected));
     }
 
     #[test]
@@ -307,97 +617,279 @@ let is_false = false;";
 }
 ";
         let expected = vec![Statement::Expression(Box::new(Expr::If {
-            condition: Box::new(Expr::Ident("x".to_string())),
+            condition: Box::new(Expr::Ident(Ident::new("x"))),
             consequence: vec![Statement::Return {
                 value: Box::new(Expr::Const(15)),
             }],
             alternative: vec![Statement::Return {
                 value: Box::new(Expr::Const(30)),
             }],
+            location: Location::default(),
         }))];
-        let mut parser = Parser::new(code);
+        assert_eq!(parse_shape(code), Ok(expected));
+    }
 
-        assert_eq!(parser.parse(), Ok(expected));
+    #[test]
+    fn parses_while_loops() {
+        let code = "while (x) { if (y) { break } continue; }";
+        let expected = vec![Statement::While {
+            condition: Box::new(Expr::Ident(Ident::new("x"))),
+            body: vec![
+                Statement::Expression(Box::new(Expr::If {
+                    condition: Box::new(Expr::Ident(Ident::new("y"))),
+                    consequence: vec![Statement::Break(Location::default())],
+                    alternative: vec![],
+                    location: Location::default(),
+                })),
+                Statement::Continue(Location::default()),
+            ],
+        }];
+        assert_eq!(parse_shape(code), Ok(expected));
+        assert_eq!(parse_shape("while x { }"), parse_shape("while (x) { }"));
+        assert!(parse_program("while (x)").is_err());
+        assert!(parse_program("let a = break;").is_err());
     }
 
     #[test]
-    fn parses_if_else_multiple_statements() {
-        let code = "
-    if (!x) {
-    let z = y;
-    let y = x;
-    let z = -1000;
-    let y = !x;
-    } else {
-    let z = -1000;
-    let y = !x;
-    let z = -1000;
-    let y = !x;
-    }
-    ";
+    fn parses_for_loops() {
+        let code = "for (i in 0..n + 1) { i }";
+        let expected = vec![Statement::For {
+            variable: Ident::new("i"),
+            iterable: Box::new(Expr::Infix {
+                left: Box::new(Expr::Const(0)),
+                operator: Token::DotDot,
+                right: Box::new(Expr::Infix {
+                    left: Box::new(Expr::Ident(Ident::new("n"))),
+                    operator: Token::Plus,
+                    right: Box::new(Expr::Const(1)),
+                    location: Location::default(),
+                }),
+                location: Location::default(),
+            }),
+            body: vec![Statement::Expression(Box::new(Expr::Ident(Ident::new("i"))))],
+        }];
+        assert_eq!(parse_shape(code), Ok(expected));
+        assert!(parse_program("for (1 in xs) {}").is_err());
+        assert!(parse_program("for (x of xs) {}").is_err());
+    }
+
+    #[test]
+    fn parses_assignments() {
+        let code = "a = b[0] += 1 + 2;";
+        let expected = vec![Statement::Expression(Box::new(Expr::Assign {
+            target: Box::new(Expr::Ident(Ident::new("a"))),
+            operator: Token::Assign,
+            value: Box::new(Expr::Assign {
+                target: Box::new(Expr::Index {
+                    left: Box::new(Expr::Ident(Ident::new("b"))),
+                    index: Box::new(Expr::Const(0)),
+                }),
+                operator: Token::PlusAssign,
+                value: Box::new(Expr::Infix {
+                    left: Box::new(Expr::Const(1)),
+                    operator: Token::Plus,
+                    right: Box::new(Expr::Const(2)),
+                    location: Location::default(),
+                }),
+            }),
+        }))];
+        assert_eq!(parse_shape(code), Ok(expected));
+        for code in ["1 = 2;", "f() = 1;", "a + b = 1;", "-a *= 2;", "f()[0] = 1;"] {
+            assert_eq!(parse_program(code), Err(AmpError::InvalidAssignmentTarget), "{}", code);
+        }
+        let err = parse_with_spans("let a = 1;\n[a] /= 2;").unwrap_err();
+        assert_eq!((err.span.line, err.span.column), (2, 5));
+    }
+
+    #[test]
+    fn parses_conditions_with_and_without_parentheses() {
+        let code = "if x < y {
+    return true;
+} else {
+    return false;
+}";
+        assert_eq!(parse_shape(code), parse_shape(&code.replace("x < y", "(x < y)")));
+
         let expected = vec![Statement::Expression(Box::new(Expr::If {
-            condition: Box::new(Expr::Prefix {
-                prefix: Token::Bang,
-                value: Box::new(Expr::Ident("x".to_string())),
+            condition: Box::new(Expr::Infix {
+                left: Box::new(Expr::Ident(Ident::new("a"))),
+                operator: Token::Or,
+                right: Box::new(Expr::Ident(Ident::new("b"))),
+                location: Location::default(),
             }),
-            consequence: vec![
-                Statement::Let {
-                    ident: Token::Identifier("z".to_string()),
-                    value: Box::new(Expr::Ident("y".to_string())),
-                },
-                Statement::Let {
-                    ident: Token::Identifier("y".to_string()),
-                    value: Box::new(Expr::Ident("x".to_string())),
-                },
-                Statement::Let {
-                    ident: Token::Identifier("z".to_string()),
-                    value: Box::new(Expr::Prefix {
-                        prefix: Token::Minus,
-                        value: Box::new(Expr::Const(1000)),
-                    }),
-                },
-                Statement::Let {
-                    ident: Token::Identifier("y".to_string()),
-                    value: Box::new(Expr::Prefix {
-                        prefix: Token::Bang,
-                        value: Box::new(Expr::Ident("x".to_string())),
-                    }),
-                },
-            ],
-            alternative: vec![
-                Statement::Let {
-                    ident: Token::Identifier("z".to_string()),
-                    value: Box::new(Expr::Prefix {
-                        prefix: Token::Minus,
-                        value: Box::new(Expr::Const(1000)),
-                    }),
-                },
-                Statement::Let {
-                    ident: Token::Identifier("y".to_string()),
-                    value: Box::new(Expr::Prefix {
-                        prefix: Token::Bang,
-                        value: Box::new(Expr::Ident("x".to_string())),
-                    }),
-                },
-                Statement::Let {
-                    ident: Token::Identifier("z".to_string()),
-                    value: Box::new(Expr::Prefix {
-                        prefix: Token::Minus,
-                        value: Box::new(Expr::Const(1000)),
-                    }),
-                },
-                Statement::Let {
-                    ident: Token::Identifier("y".to_string()),
-                    value: Box::new(Expr::Prefix {
-                        prefix: Token::Bang,
-                        value: Box::new(Expr::Ident("x".to_string())),
-                    }),
-                },
-            ],
+            consequence: vec![],
+            alternative: vec![],
+            location: Location::default(),
         }))];
-        let mut parser = Parser::new(code);
+        assert_eq!(parse_shape("if (a) || b {}"), Ok(expected.clone()));
+        assert_eq!(parse_shape("if ((a) || (b)) {}"), Ok(expected));
+        assert!(parse_program("if x").is_err());
+        assert!(parse_program("if (x {}").is_err());
+    }
+
+    #[test]
+    fn parses_else_if_chains() {
+        let (program, spans) = parse_with_spans("if (a) { 1 } else if (b) { 2 } else if (c) { 3 } else { 4 }").unwrap();
+        let branch = |condition: &str, value: u64, alternative: Vec<Statement>| Expr::If {
+            condition: Box::new(Expr::Ident(Ident::new(condition))),
+            consequence: vec![Statement::Expression(Box::new(Expr::Const(value)))],
+            alternative,
+            location: Location::default(),
+        };
+        let innermost = branch("c", 3, vec![Statement::Expression(Box::new(Expr::Const(4)))]);
+        let middle = branch("b", 2, vec![Statement::Expression(Box::new(innermost))]);
+        let expected = vec![Statement::Expression(Box::new(branch(
+            "a",
+            1,
+            vec![Statement::Expression(Box::new(middle))],
+        )))];
+
+        assert_eq!(strip_locations(program), expected);
+        // The statements of the chain in pre-order: the outer `if`, `1`, `else if (b)`, `2`, ...
+        let columns: Vec<usize> = spans.iter().map(|span| span.column).collect();
+        assert_eq!(columns, vec![1, 10, 19, 28, 37, 46, 57]);
+        assert!(parse_program("if (a) { 1 } else if { 2 }").is_err());
+        assert!(parse_program("if (a) { 1 } else 2").is_err());
+    }
+
+    #[test]
+    fn parses_block_expressions() {
+        let (program, spans) = parse_with_spans("let y = { let x = 1; x };\n{ f(x); }").unwrap();
+        let expected = vec![
+            Statement::Let {
+                ident: Ident::new("y"),
+                value: Box::new(Expr::Block(vec![
+                    Statement::Let {
+                        ident: Ident::new("x"),
+                        value: Box::new(Expr::Const(1)),
+                    },
+                    Statement::Expression(Box::new(Expr::Ident(Ident::new("x")))),
+                ])),
+            },
+            Statement::Expression(Box::new(Expr::Block(vec![
+                Statement::Expression(Box::new(Expr::Call {
+                    function: Box::new(Expr::Ident(Ident::new("f"))),
+                    arguments: vec![Expr::Ident(Ident::new("x"))],
+                })),
+                Statement::Empty,
+            ]))),
+        ];
+        assert_eq!(strip_locations(program), expected);
+        // The trailing `;` is the empty statement that ends the second block.
+        let positions: Vec<(usize, usize)> = spans.iter().map(|span| (span.line, span.column)).collect();
+        assert_eq!(positions, vec![(1, 1), (1, 11), (1, 22), (2, 1), (2, 3), (2, 7)]);
+
+        assert_eq!(
+            parse_shape("{ x }; { x: 1 }; {}").unwrap(),
+            vec![
+                Statement::Expression(Box::new(Expr::Block(vec![Statement::Expression(Box::new(
+                    Expr::Ident(Ident::new("x"))
+                ))]))),
+                Statement::Expression(Box::new(Expr::Hash(vec![(
+                    Expr::Ident(Ident::new("x")),
+                    Expr::Const(1)
+                )]))),
+                Statement::Expression(Box::new(Expr::Hash(Vec::new()))),
+            ]
+        );
+        // Only a semicolon at the end of a block adds an empty statement.
+        assert_eq!(
+            parse_program("{ a; b;; }").unwrap(),
+            parse_program("{ a; b; }").unwrap()
+        );
+    }
+
+    #[test]
+    fn records_identifier_and_jump_locations() {
+        let program = parse_program("for (i in xs) {\n  f(i); break;\n}").unwrap();
+        let position = |location: &Location| location.0.map(|span| (span.line, span.column));
+        match &program[0] {
+            Statement::For {
+                variable,
+                iterable,
+                body,
+            } => {
+                assert_eq!(position(&variable.location), Some((1, 6)));
+                assert!(matches!(iterable.as_ref(), Expr::Ident(xs) if position(&xs.location) == Some((1, 11))));
+                match (&body[0], &body[1]) {
+                    (Statement::Expression(call), Statement::Break(location)) => {
+                        assert!(matches!(call.as_ref(), Expr::Call { arguments, .. }
+                            if matches!(&arguments[0], Expr::Ident(i) if position(&i.location) == Some((2, 5)))));
+                        assert_eq!(position(location), Some((2, 9)));
+                    }
+                    statements => panic!("unexpected body {:?}", statements),
+                }
+            }
+            statement => panic!("unexpected statement {:?}", statement),
+        }
+    }
 
-        assert_eq!(parser.parse(), Ok(expected));
+    #[test]
+    fn reports_error_locations() {
+        let err = parse_with_spans("let x = 1;\nlet y = 2 3;").unwrap_err();
+        assert_eq!(err.error, AmpError::InvalidToken(Token::Integer(3), Token::SemiColon));
+        assert_eq!((err.span.line, err.span.column), (2, 11));
+
+        let err = parse_with_spans("let x = ;").unwrap_err();
+        assert_eq!(err.error, AmpError::UnexpectedToken(Token::SemiColon));
+        assert_eq!((err.span.line, err.span.column), (1, 9));
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        // Regression inputs from fuzzing, each used to overflow the stack, and an `else if` chain.
+        let inputs = [
+            "!".repeat(100_000),
+            "-".repeat(100_000) + "1",
+            "(".repeat(100_000),
+            "[".repeat(100_000),
+            "{".repeat(100_000),
+            "if (x) { ".repeat(10_000),
+            "fn() { ".repeat(10_000),
+            "a[".repeat(10_000),
+            "if (x) {} else ".repeat(10_000),
+            "1".to_string() + &"+1".repeat(100_000),
+            "x".to_string() + &"[0]".repeat(200_000),
+        ];
+        for input in inputs.iter() {
+            assert!(
+                matches!(parse_program(input), Err(AmpError::NestingTooDeep { limit: 128, .. })),
+                "{}...",
+                &input[..10]
+            );
+        }
+
+        let err = parse_program_with_max_nesting("let x = 1 + -(2 * f(-3));", 10).unwrap_err();
+        assert_eq!(
+            err,
+            AmpError::NestingTooDeep {
+                limit: 10,
+                line: 1,
+                column: 22
+            }
+        );
+        assert_eq!(err.to_string(), "expressions nested deeper than 10 levels at 1:22");
+        assert!(parse_program_with_max_nesting("let x = 1 + -(2 * f(-3));", 11).is_ok());
+        assert!(parse_program(&format!("{}1{}", "(".repeat(100), ")".repeat(100))).is_ok());
+        assert!(parse_program(&format!("{}{}", "if (x) { ".repeat(60), "}".repeat(60))).is_ok());
+
+        // Programs within the limit must not overflow later passes either.
+        let deepest = format!("{}1{}", "if (true) { -".repeat(42), "}".repeat(42));
+        assert_eq!(crate::formatter::format(&deepest).map(|_| ()), Ok(()));
+        assert!(crate::eval::Evaluator::new().eval(&deepest).is_ok());
+    }
+
+    #[test]
+    fn rejects_unbalanced_blocks() {
+        assert!(parse_program("[1, 2").is_err());
+        assert!(parse_program("a[1").is_err());
+        assert!(parse_program(r#"{"a": 1, "b"}"#).is_err());
+        assert!(parse_program(r#"{"a": 1"#).is_err());
+        assert!(parse_program("if (x) { 1 ").is_err());
+        assert!(parse_program("fn() { { 5 }").is_err());
+        assert!(parse_program("{ 5 ").is_err());
+        assert!(parse_program("1; }").is_err());
+        assert!(parse_program("add(1, 2").is_err());
     }
 
     #[test]
@@ -416,8 +908,6 @@ let is_false = false;";
                 value: Box::new(Expr::Boolean(true)),
             })),
         ];
-        let mut parser = Parser::new(code);
-
-        assert_eq!(parser.parse(), Ok(expected));
+        assert_eq!(parse_shape(code), Ok(expected));
     }
 }
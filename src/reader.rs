@@ -1,62 +1,87 @@
+/// Cursor over the characters of a source text. Positions and columns are byte offsets, so they
+/// can be used to slice the source.
 pub(crate) struct Reader<'r> {
-    inner: &'r [u8],
+    inner: &'r str,
     position: usize,
-    len: usize,
+    line: usize,
+    line_start: usize,
 }
 impl<'r> Reader<'r> {
     pub(crate) fn new(text: &'r str) -> Self {
         Reader {
-            inner: text.as_bytes(),
+            inner: text,
             position: 0,
-            len: text.len(),
+            line: 1,
+            line_start: 0,
         }
     }
 
+    /// Returns the character under the cursor or `None` once the whole input was consumed.
     #[inline]
-    pub(crate) fn current(&mut self) -> char {
-        assert!(self.position < self.len);
-        self.inner[self.position] as char
+    pub(crate) fn current(&self) -> Option<char> {
+        self.inner[self.position..].chars().next()
     }
 
     #[inline]
     pub(crate) fn next(&mut self) -> Option<char> {
-        if self.position < self.len - 1 {
-            self.position += 1;
-            return Some(self.current());
-        }
-
-        None
+        self.advance();
+        self.current()
     }
 
     #[inline]
     pub(crate) fn peek(&self) -> Option<char> {
-        if self.position < self.len - 1 {
-            return Some(self.inner[self.position + 1] as char);
-        }
-
-        None
+        self.inner[self.position..].chars().nth(1)
     }
 
     #[inline]
     pub(crate) fn skip(&mut self, n: usize) {
-        if self.position + n >= self.len {
-            self.position = self.len - 1;
-        } else {
-            self.position += n;
+        for _ in 0..n {
+            self.advance();
         }
     }
 
     #[inline]
     pub(crate) fn skip_whitespace(&mut self) {
-        while self.current().is_ascii_whitespace() {
-            if let None = self.next() {
+        while let Some(ch) = self.current() {
+            if !ch.is_ascii_whitespace() {
                 break;
             }
+            self.advance();
         }
     }
 
     #[inline]
-    pub(crate) fn is_last(&self) -> bool {
-        self.position == (self.len - 1)
+    pub(crate) fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Source text between the byte offsets `start` and `end`.
+    pub(crate) fn slice(&self, start: usize, end: usize) -> &'r str {
+        &self.inner[start..end]
+    }
+
+    /// 1-based line of the cursor.
+    #[inline]
+    pub(crate) fn line(&self) -> usize {
+        self.line
+    }
+
+    /// 1-based column of the cursor.
+    #[inline]
+    pub(crate) fn column(&self) -> usize {
+        self.position - self.line_start + 1
+    }
+
+    #[inline]
+    fn advance(&mut self) {
+        let ch = match self.current() {
+            Some(ch) => ch,
+            None => return,
+        };
+        if ch == '\n' {
+            self.line += 1;
+            self.line_start = self.position + 1;
+        }
+        self.position += ch.len_utf8();
     }
 }
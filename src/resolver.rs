@@ -0,0 +1,564 @@
+//! Static name resolution.
+//!
+//! The resolver walks a parsed program, keeps a table of the bindings visible in every scope and
+//! checks that each identifier refers to a `let` binding or a function parameter. Identifiers bound
+//! in a local scope are rewritten into `Expr::Local` so the evaluator can find them by their
+//! scope depth and slot instead of looking them up by name.
+//!
+//! Scopes are opened by function bodies (parameters take the first slots), by the branches of
+//! an `if`, by block expressions and by loop bodies (the variable of a `for` takes the first
+//! slot). Top level bindings are globals - they stay `Expr::Ident` and may be referenced from
+//! function bodies before they are declared.
+use super::{
+    ast::{Expr, Ident, Location, Span, Statement},
+    builtins,
+    diagnostic::Diagnostic,
+    parser::parse_with_spans,
+};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BindingKind {
+    /// A top level `let`.
+    Global,
+    /// A `let` inside of a function body or a block, or the variable of a `for` loop.
+    Local,
+    Parameter,
+    /// A name declared by the host through `Resolver::declare`.
+    External,
+    /// A function from the `builtins` module. Programs can shadow it with their own binding.
+    Builtin,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Binding {
+    pub name: String,
+    pub kind: BindingKind,
+    /// Index into `Resolution::occurrences` of the declaring identifier. `None` for external and
+    /// builtin bindings.
+    pub declaration: Option<usize>,
+    /// The binding with the same name that was visible where this one got declared.
+    pub shadows: Option<usize>,
+}
+
+/// A single identifier in the program, in source order. The span is the location of the
+/// identifier in the tree.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Occurrence {
+    pub name: String,
+    pub span: Option<Span>,
+    /// Index into `Resolution::bindings`, `None` if the name is undefined.
+    pub binding: Option<usize>,
+}
+
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Resolution {
+    pub bindings: Vec<Binding>,
+    pub occurrences: Vec<Occurrence>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Resolution {
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(Diagnostic::is_error)
+    }
+
+    /// Indices of all occurrences that use `binding`, without its declaration.
+    pub fn references(&self, binding: usize) -> Vec<usize> {
+        let declaration = self.bindings[binding].declaration;
+        self.occurrences
+            .iter()
+            .enumerate()
+            .filter(|(i, occurrence)| occurrence.binding == Some(binding) && Some(*i) != declaration)
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+/// Resolves `program` without any predeclared names.
+pub fn resolve(program: &mut [Statement]) -> Resolution {
+    Resolver::new().resolve(program)
+}
+
+/// Parses and resolves `source` with `externals` declared as globals. A parse error is returned as
+/// the only diagnostic.
+pub fn diagnostics(source: &str, externals: &[&str]) -> Vec<Diagnostic> {
+    match parse_with_spans(source) {
+        Ok((mut program, _)) => {
+            let mut resolver = Resolver::new();
+            for name in externals {
+                resolver.declare(name);
+            }
+            resolver.resolve(&mut program).diagnostics
+        }
+        Err(e) => vec![Diagnostic::error(e.error.to_string(), Some(e.span))],
+    }
+}
+
+#[derive(Default)]
+pub struct Resolver {
+    /// Local scopes, innermost last. Each entry is a `(name, binding)` pair and its index is the slot.
+    scopes: Vec<Vec<(String, usize)>>,
+    globals: HashMap<String, usize>,
+    /// Bindings of the builtins used so far.
+    builtins: HashMap<String, usize>,
+    hoisted: HashSet<String>,
+    /// Global references made from function bodies before the global was declared.
+    pending: Vec<usize>,
+    functions: usize,
+    /// Loops around the current statement, within the innermost function.
+    loops: usize,
+    resolution: Resolution,
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver::default()
+    }
+
+    /// Declares a global that is defined outside of the resolved program, e.g. by an earlier
+    /// input of an interactive session.
+    pub fn declare(&mut self, name: &str) {
+        let binding = self.add_binding(name, BindingKind::External, None);
+        self.globals.insert(name.to_string(), binding);
+    }
+
+    pub fn resolve(mut self, program: &mut [Statement]) -> Resolution {
+        for statement in program.iter() {
+            if let Statement::Let { ident, .. } = statement {
+                self.hoisted.insert(ident.name.clone());
+            }
+        }
+
+        self.resolve_statements(program);
+
+        for occurrence in std::mem::take(&mut self.pending) {
+            let name = &self.resolution.occurrences[occurrence].name;
+            self.resolution.occurrences[occurrence].binding = self.globals.get(name).copied();
+        }
+
+        self.resolution
+    }
+
+    fn resolve_statements(&mut self, statements: &mut [Statement]) {
+        for statement in statements {
+            self.resolve_statement(statement);
+        }
+    }
+
+    fn resolve_statement(&mut self, statement: &mut Statement) {
+        match statement {
+            Statement::Let { ident, value } => {
+                let occurrence = self.add_occurrence(ident);
+                // Functions may call themselves, every other initializer sees the outer binding.
+                if let Expr::Function { .. } = value.as_ref() {
+                    self.declare_let(&ident.name, occurrence);
+                    self.resolve_expr(value);
+                } else {
+                    self.resolve_expr(value);
+                    self.declare_let(&ident.name, occurrence);
+                }
+            }
+            Statement::Expression(expr) => self.resolve_expr(expr),
+            Statement::Return { value } => self.resolve_expr(value),
+            Statement::While { condition, body } => {
+                self.resolve_expr(condition);
+                self.loops += 1;
+                self.resolve_block(body);
+                self.loops -= 1;
+            }
+            Statement::For {
+                variable,
+                iterable,
+                body,
+            } => {
+                let occurrence = self.add_occurrence(variable);
+                self.resolve_expr(iterable);
+                self.loops += 1;
+                self.scopes.push(Vec::new());
+                self.declare_let(&variable.name, occurrence);
+                self.resolve_statements(body);
+                self.scopes.pop();
+                self.loops -= 1;
+            }
+            Statement::Break(location) => self.resolve_jump("break", *location),
+            Statement::Continue(location) => self.resolve_jump("continue", *location),
+            Statement::Empty => {}
+        }
+    }
+
+    fn resolve_jump(&mut self, keyword: &str, location: Location) {
+        if self.loops == 0 {
+            let message = format!("`{}` outside of a loop", keyword);
+            self.resolution.diagnostics.push(Diagnostic::error(message, location.0));
+        }
+    }
+
+    fn resolve_block(&mut self, statements: &mut [Statement]) {
+        self.scopes.push(Vec::new());
+        self.resolve_statements(statements);
+        self.scopes.pop();
+    }
+
+    fn resolve_expr(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::Ident(name) | Expr::Local { name, .. } => {
+                let ident = name.clone();
+                *expr = self.resolve_name(ident);
+            }
+            Expr::Prefix { value, .. } => self.resolve_expr(value),
+            Expr::Infix { left, right, .. } => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+            Expr::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => {
+                self.resolve_expr(condition);
+                self.resolve_block(consequence);
+                self.resolve_block(alternative);
+            }
+            Expr::Block(body) => self.resolve_block(body),
+            Expr::Function { parameters, body } => {
+                self.functions += 1;
+                // Loops around a function don't extend into its body.
+                let loops = std::mem::take(&mut self.loops);
+                self.scopes.push(Vec::new());
+                for parameter in parameters.iter() {
+                    let occurrence = self.add_occurrence(parameter);
+                    let duplicate = self.current_scope().iter().any(|(name, _)| name == &parameter.name);
+                    // Duplicate parameters don't shadow each other, they are an error.
+                    let shadows = if duplicate {
+                        self.error(format!("duplicate parameter `{}`", parameter), occurrence);
+                        None
+                    } else {
+                        self.lookup(&parameter.name)
+                    };
+                    let binding = self.add_binding(&parameter.name, BindingKind::Parameter, Some(occurrence));
+                    self.resolution.bindings[binding].shadows = shadows;
+                    self.resolution.occurrences[occurrence].binding = Some(binding);
+                    self.current_scope().push((parameter.name.clone(), binding));
+                }
+                self.resolve_statements(body);
+                self.scopes.pop();
+                self.loops = loops;
+                self.functions -= 1;
+            }
+            Expr::Call { function, arguments } => {
+                self.resolve_expr(function);
+                for argument in arguments {
+                    self.resolve_expr(argument);
+                }
+            }
+            Expr::Array(elements) => {
+                for element in elements {
+                    self.resolve_expr(element);
+                }
+            }
+            Expr::Hash(pairs) => {
+                for (key, value) in pairs {
+                    self.resolve_expr(key);
+                    self.resolve_expr(value);
+                }
+            }
+            Expr::Index { left, index } => {
+                self.resolve_expr(left);
+                self.resolve_expr(index);
+            }
+            Expr::Assign { target, value, .. } => {
+                let occurrence = self.resolution.occurrences.len();
+                self.resolve_expr(target);
+                // The parser only accepts targets that start with an identifier.
+                let binding = self.resolution.occurrences.get(occurrence).and_then(|o| o.binding);
+                if let Some(binding) = binding {
+                    if self.resolution.bindings[binding].kind == BindingKind::Builtin {
+                        let message = format!("cannot assign to builtin `{}`", self.resolution.bindings[binding].name);
+                        self.error(message, occurrence);
+                    }
+                }
+                self.resolve_expr(value);
+            }
+            Expr::Const(_) | Expr::String(_) | Expr::Boolean(_) | Expr::Unknown => {}
+        }
+    }
+
+    fn resolve_name(&mut self, ident: Ident) -> Expr {
+        let occurrence = self.add_occurrence(&ident);
+        let name = &ident.name;
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(slot) = scope.iter().rposition(|(bound, _)| bound == name) {
+                self.resolution.occurrences[occurrence].binding = Some(scope[slot].1);
+                return Expr::Local {
+                    name: ident,
+                    depth,
+                    slot,
+                };
+            }
+        }
+
+        if let Some(&binding) = self.globals.get(name) {
+            self.resolution.occurrences[occurrence].binding = Some(binding);
+        } else if self.functions > 0 && self.hoisted.contains(name) {
+            self.pending.push(occurrence);
+        } else if builtins::is_builtin(name) {
+            let binding = match self.builtins.get(name) {
+                Some(&binding) => binding,
+                None => {
+                    let binding = self.add_binding(name, BindingKind::Builtin, None);
+                    self.builtins.insert(name.clone(), binding);
+                    binding
+                }
+            };
+            self.resolution.occurrences[occurrence].binding = Some(binding);
+        } else {
+            self.error(format!("undefined variable `{}`", name), occurrence);
+        }
+        Expr::Ident(ident)
+    }
+
+    fn declare_let(&mut self, name: &str, occurrence: usize) {
+        let shadows = self.lookup(name);
+        let kind = if self.scopes.is_empty() {
+            BindingKind::Global
+        } else {
+            BindingKind::Local
+        };
+        let binding = self.add_binding(name, kind, Some(occurrence));
+        self.resolution.bindings[binding].shadows = shadows;
+        self.resolution.occurrences[occurrence].binding = Some(binding);
+        if self.scopes.is_empty() {
+            self.globals.insert(name.to_string(), binding);
+        } else {
+            self.current_scope().push((name.to_string(), binding));
+        }
+    }
+
+    /// Finds the binding currently visible under `name`.
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.iter().rev().find(|(bound, _)| bound == name))
+            .map(|(_, binding)| *binding)
+            .or_else(|| self.globals.get(name).copied())
+    }
+
+    fn current_scope(&mut self) -> &mut Vec<(String, usize)> {
+        self.scopes.last_mut().expect("no local scope")
+    }
+
+    fn add_binding(&mut self, name: &str, kind: BindingKind, declaration: Option<usize>) -> usize {
+        self.resolution.bindings.push(Binding {
+            name: name.to_string(),
+            kind,
+            declaration,
+            shadows: None,
+        });
+        self.resolution.bindings.len() - 1
+    }
+
+    fn add_occurrence(&mut self, ident: &Ident) -> usize {
+        self.resolution.occurrences.push(Occurrence {
+            name: ident.name.clone(),
+            span: ident.location.0,
+            binding: None,
+        });
+        self.resolution.occurrences.len() - 1
+    }
+
+    fn error(&mut self, message: String, occurrence: usize) {
+        let span = self.resolution.occurrences[occurrence].span;
+        self.resolution.diagnostics.push(Diagnostic::error(message, span));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast::Token, parser::parse_program, visit::strip_locations};
+
+    fn resolve_source(source: &str) -> (Vec<Statement>, Resolution) {
+        let mut program = parse_program(source).unwrap();
+        let resolution = Resolver::new().resolve(&mut program);
+        (program, resolution)
+    }
+
+    #[test]
+    fn reports_undefined_variables() {
+        let (_, resolution) = resolve_source(
+            "let x = 5;
+let add = fn(a) { a + y };
+z;",
+        );
+        let messages: Vec<String> = resolution.diagnostics.iter().map(|d| d.to_string()).collect();
+
+        assert_eq!(
+            messages,
+            vec![
+                "2:23: error: undefined variable `y`".to_string(),
+                "3:1: error: undefined variable `z`".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_duplicate_parameters() {
+        let (_, resolution) = resolve_source("let f = fn(a, b, a) { a + b };");
+
+        assert_eq!(resolution.diagnostics.len(), 1);
+        assert_eq!(resolution.diagnostics[0].message, "duplicate parameter `a`");
+        assert_eq!(resolution.diagnostics[0].span.unwrap().column, 18);
+    }
+
+    #[test]
+    fn reports_jumps_outside_of_loops() {
+        let (_, resolution) = resolve_source(
+            "while (true) {
+    let f = fn() { continue; };
+    if (true) { break; }
+}
+break;",
+        );
+        let messages: Vec<String> = resolution.diagnostics.iter().map(|d| d.to_string()).collect();
+
+        assert_eq!(
+            messages,
+            vec![
+                "2:20: error: `continue` outside of a loop".to_string(),
+                "5:1: error: `break` outside of a loop".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_invalid_assignments() {
+        let (_, resolution) = resolve_source(
+            "let f = fn() { later = 1; len = 2; };
+missing += 1;
+let later = 0;",
+        );
+        let messages: Vec<String> = resolution.diagnostics.iter().map(|d| d.to_string()).collect();
+
+        assert_eq!(
+            messages,
+            vec![
+                "1:27: error: cannot assign to builtin `len`".to_string(),
+                "2:1: error: undefined variable `missing`".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn annotates_locals_with_depth_and_slot() {
+        let (program, resolution) = resolve_source(
+            "let g = 1;
+let f = fn(a, b) {
+    let c = a;
+    if (b) {
+        c + g;
+    }
+};",
+        );
+        assert!(!resolution.has_errors());
+
+        let expected = Statement::Let {
+            ident: Ident::new("f"),
+            value: Box::new(Expr::Function {
+                parameters: vec![Ident::new("a"), Ident::new("b")],
+                body: vec![
+                    Statement::Let {
+                        ident: Ident::new("c"),
+                        value: Box::new(Expr::Local {
+                            name: Ident::new("a"),
+                            depth: 0,
+                            slot: 0,
+                        }),
+                    },
+                    Statement::Expression(Box::new(Expr::If {
+                        condition: Box::new(Expr::Local {
+                            name: Ident::new("b"),
+                            depth: 0,
+                            slot: 1,
+                        }),
+                        consequence: vec![
+                            Statement::Expression(Box::new(Expr::Infix {
+                                left: Box::new(Expr::Local {
+                                    name: Ident::new("c"),
+                                    depth: 1,
+                                    slot: 2,
+                                }),
+                                operator: Token::Plus,
+                                right: Box::new(Expr::Ident(Ident::new("g"))),
+                                location: Location::default(),
+                            })),
+                            Statement::Empty,
+                        ],
+                        alternative: vec![],
+                        location: Location::default(),
+                    })),
+                ],
+            }),
+        };
+        assert_eq!(strip_locations(program)[1], expected);
+    }
+
+    #[test]
+    fn allows_recursion_and_forward_references_from_functions() {
+        let (_, resolution) = resolve_source(
+            "let even = fn(n) { if (n == 0) { true } else { odd(n - 1) } };
+let odd = fn(n) { if (n == 0) { false } else { even(n - 1) } };
+let outer = fn() { let inner = fn(n) { inner(n) }; inner };",
+        );
+        assert!(!resolution.has_errors(), "{:?}", resolution.diagnostics);
+
+        let odd = resolution.occurrences.iter().find(|o| o.name == "odd").unwrap();
+        assert_eq!(resolution.bindings[odd.binding.unwrap()].kind, BindingKind::Global);
+        assert_eq!(resolution.references(odd.binding.unwrap()).len(), 1);
+    }
+
+    #[test]
+    fn records_shadowed_bindings() {
+        let (_, resolution) = resolve_source("let x = 1; let f = fn(x) { let x = x; x };");
+        let shadows: Vec<Option<usize>> = resolution.bindings.iter().map(|b| b.shadows).collect();
+
+        // x, f, parameter x, inner x
+        assert_eq!(shadows, vec![None, None, Some(0), Some(2)]);
+    }
+
+    #[test]
+    fn rejects_top_level_use_before_declaration() {
+        let (_, resolution) = resolve_source("x; let x = 1;");
+
+        assert_eq!(resolution.diagnostics.len(), 1);
+        assert_eq!(resolution.diagnostics[0].message, "undefined variable `x`");
+    }
+
+    #[test]
+    fn accepts_declared_externals() {
+        let mut program = parse_program("x + 1;").unwrap();
+        let mut resolver = Resolver::new();
+        resolver.declare("x");
+        let resolution = resolver.resolve(&mut program);
+
+        assert!(!resolution.has_errors());
+        assert_eq!(resolution.bindings[0].kind, BindingKind::External);
+        assert_eq!(resolution.occurrences[0].binding, Some(0));
+    }
+
+    #[test]
+    fn resolves_builtins_unless_shadowed() {
+        let (_, resolution) = resolve_source("len([1]); len([]); let f = fn(len) { len };");
+        let kinds: Vec<BindingKind> = resolution.bindings.iter().map(|b| b.kind).collect();
+
+        assert!(!resolution.has_errors());
+        assert_eq!(
+            kinds,
+            vec![BindingKind::Builtin, BindingKind::Global, BindingKind::Parameter]
+        );
+        assert_eq!(resolution.references(0), vec![0, 1]);
+        assert_eq!(resolution.occurrences[4].binding, Some(2));
+    }
+}
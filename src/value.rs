@@ -0,0 +1,207 @@
+use super::{
+    ast::Statement,
+    eval::{Env, Evaluator},
+    AmpError,
+};
+use std::{collections::BTreeMap, fmt, rc::Rc};
+
+/// A runtime value.
+///
+/// Only `false` and `null` are falsy, every other value - including `0`, `""`, `[]` and `{}` - is
+/// truthy. Truthiness is what `if`, `while`, `!`, `&&` and `||` test.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Integer(i64),
+    Boolean(bool),
+    String(String),
+    Function(Rc<Function>),
+    Builtin(Rc<Builtin>),
+    Array(Rc<Vec<Value>>),
+    /// Entries are kept sorted by key. Looking up a missing key yields `null`.
+    Hash(Rc<BTreeMap<HashKey, Value>>),
+    /// Integers from `start` up to `end`, created by `start..end` or `start..=end`.
+    Range {
+        start: i64,
+        end: i64,
+        inclusive: bool,
+    },
+    Null,
+}
+
+/// A value that can be used as a hash key: integers, booleans and strings.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HashKey {
+    Integer(i64),
+    Boolean(bool),
+    String(String),
+}
+
+impl HashKey {
+    pub fn from_value(value: &Value) -> Result<HashKey, AmpError> {
+        match value {
+            Value::Integer(n) => Ok(HashKey::Integer(*n)),
+            Value::Boolean(b) => Ok(HashKey::Boolean(*b)),
+            Value::String(s) => Ok(HashKey::String(s.clone())),
+            other => Err(AmpError::UnhashableKey(other.type_name())),
+        }
+    }
+}
+
+impl From<HashKey> for Value {
+    fn from(key: HashKey) -> Value {
+        match key {
+            HashKey::Integer(n) => Value::Integer(n),
+            HashKey::Boolean(b) => Value::Boolean(b),
+            HashKey::String(s) => Value::String(s),
+        }
+    }
+}
+
+pub struct Function {
+    pub parameters: Vec<String>,
+    pub body: Vec<Statement>,
+    /// Scope the function was created in, `None` for functions defined at the top level.
+    pub(crate) env: Option<Env>,
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The captured scope may contain the function itself.
+        write!(f, "fn({})", self.parameters.join(", "))
+    }
+}
+
+pub type NativeFunction = dyn Fn(&mut Evaluator, &[Value]) -> Result<Value, AmpError>;
+
+/// A function implemented in Rust.
+pub struct Builtin {
+    pub name: String,
+    pub(crate) function: Box<NativeFunction>,
+}
+
+impl Builtin {
+    pub fn new<F>(name: &str, function: F) -> Builtin
+    where
+        F: Fn(&mut Evaluator, &[Value]) -> Result<Value, AmpError> + 'static,
+    {
+        Builtin {
+            name: name.to_string(),
+            function: Box::new(function),
+        }
+    }
+}
+
+impl fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "builtin {}", self.name)
+    }
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Integer(_) => "integer",
+            Value::Boolean(_) => "boolean",
+            Value::String(_) => "string",
+            Value::Function(_) | Value::Builtin(_) => "function",
+            Value::Array(_) => "array",
+            Value::Hash(_) => "hash",
+            Value::Range { .. } => "range",
+            Value::Null => "null",
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Boolean(false) | Value::Null)
+    }
+
+    /// Iterates the value in a `for` loop: the elements of an array, the characters of a string,
+    /// the keys of a hash in order or the integers of a range. New iterable types add an arm here.
+    pub fn iter(&self) -> Result<Box<dyn Iterator<Item = Value>>, AmpError> {
+        match self {
+            Value::Array(values) => {
+                let values = values.clone();
+                Ok(Box::new((0..values.len()).map(move |i| values[i].clone())))
+            }
+            Value::String(s) => {
+                let characters: Vec<Value> = s.chars().map(|ch| Value::String(ch.to_string())).collect();
+                Ok(Box::new(characters.into_iter()))
+            }
+            Value::Hash(entries) => {
+                let keys: Vec<Value> = entries.keys().cloned().map(Value::from).collect();
+                Ok(Box::new(keys.into_iter()))
+            }
+            &Value::Range {
+                start,
+                end,
+                inclusive: false,
+            } => Ok(Box::new((start..end).map(Value::Integer))),
+            &Value::Range {
+                start,
+                end,
+                inclusive: true,
+            } => Ok(Box::new((start..=end).map(Value::Integer))),
+            other => Err(AmpError::NotIterable(other.type_name())),
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Builtin(a), Value::Builtin(b)) => Rc::ptr_eq(a, b),
+            (Value::Array(a), Value::Array(b)) => a == b,
+            (Value::Hash(a), Value::Hash(b)) => a == b,
+            (
+                Value::Range { start, end, inclusive },
+                Value::Range {
+                    start: other_start,
+                    end: other_end,
+                    inclusive: other_inclusive,
+                },
+            ) => (start, end, inclusive) == (other_start, other_end, other_inclusive),
+            (Value::Null, Value::Null) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Function(function) => write!(f, "fn({}) {{ ... }}", function.parameters.join(", ")),
+            Value::Builtin(builtin) => write!(f, "<builtin {}>", builtin.name),
+            Value::Array(values) => {
+                f.write_str("[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_str("]")
+            }
+            Value::Hash(entries) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}: {}", Value::from(key.clone()), value)?;
+                }
+                f.write_str("}")
+            }
+            Value::Range { start, end, inclusive } => {
+                write!(f, "{}{}{}", start, if *inclusive { "..=" } else { ".." }, end)
+            }
+            Value::Null => f.write_str("null"),
+        }
+    }
+}
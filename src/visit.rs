@@ -0,0 +1,471 @@
+//! Traversal of syntax trees.
+//!
+//! `Visitor` walks a tree by reference, `VisitorMut` walks it by mutable reference and `Fold`
+//! consumes it and builds a new one. Every trait method defaults to the matching free function
+//! (`walk_expr`, `walk_expr_mut`, `fold_expr`, ...) which visits the children of the node, so an
+//! implementation only overrides the nodes it cares about and calls the free function to keep
+//! descending.
+//!
+//! Children are visited in source order, which keeps the walk in step with the statement spans of
+//! `parse_with_spans`.
+use super::ast::{Expr, Ident, Location, Statement};
+
+pub trait Visitor {
+    fn visit_block(&mut self, block: &[Statement]) {
+        walk_block(self, block)
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement)
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr)
+    }
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, block: &[Statement]) {
+    for statement in block {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Statement) {
+    match statement {
+        Statement::Let { value, .. } | Statement::Expression(value) | Statement::Return { value } => {
+            visitor.visit_expr(value)
+        }
+        Statement::While { condition, body } => {
+            visitor.visit_expr(condition);
+            visitor.visit_block(body);
+        }
+        Statement::For { iterable, body, .. } => {
+            visitor.visit_expr(iterable);
+            visitor.visit_block(body);
+        }
+        Statement::Break(_) | Statement::Continue(_) | Statement::Empty => {}
+    }
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Prefix { value, .. } => visitor.visit_expr(value),
+        Expr::Infix { left, right, .. } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        }
+        Expr::If {
+            condition,
+            consequence,
+            alternative,
+            ..
+        } => {
+            visitor.visit_expr(condition);
+            visitor.visit_block(consequence);
+            visitor.visit_block(alternative);
+        }
+        Expr::Function { body, .. } | Expr::Block(body) => visitor.visit_block(body),
+        Expr::Call { function, arguments } => {
+            visitor.visit_expr(function);
+            for argument in arguments {
+                visitor.visit_expr(argument);
+            }
+        }
+        Expr::Array(elements) => {
+            for element in elements {
+                visitor.visit_expr(element);
+            }
+        }
+        Expr::Hash(pairs) => {
+            for (key, value) in pairs {
+                visitor.visit_expr(key);
+                visitor.visit_expr(value);
+            }
+        }
+        Expr::Index { left, index } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(index);
+        }
+        Expr::Assign { target, value, .. } => {
+            visitor.visit_expr(target);
+            visitor.visit_expr(value);
+        }
+        Expr::Const(_) | Expr::String(_) | Expr::Boolean(_) | Expr::Ident(_) | Expr::Local { .. } | Expr::Unknown => {}
+    }
+}
+
+pub trait VisitorMut {
+    fn visit_block_mut(&mut self, block: &mut [Statement]) {
+        walk_block_mut(self, block)
+    }
+
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        walk_statement_mut(self, statement)
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr)
+    }
+}
+
+pub fn walk_block_mut<V: VisitorMut + ?Sized>(visitor: &mut V, block: &mut [Statement]) {
+    for statement in block {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+pub fn walk_statement_mut<V: VisitorMut + ?Sized>(visitor: &mut V, statement: &mut Statement) {
+    match statement {
+        Statement::Let { value, .. } | Statement::Expression(value) | Statement::Return { value } => {
+            visitor.visit_expr_mut(value)
+        }
+        Statement::While { condition, body } => {
+            visitor.visit_expr_mut(condition);
+            visitor.visit_block_mut(body);
+        }
+        Statement::For { iterable, body, .. } => {
+            visitor.visit_expr_mut(iterable);
+            visitor.visit_block_mut(body);
+        }
+        Statement::Break(_) | Statement::Continue(_) | Statement::Empty => {}
+    }
+}
+
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expr) {
+    match expr {
+        Expr::Prefix { value, .. } => visitor.visit_expr_mut(value),
+        Expr::Infix { left, right, .. } => {
+            visitor.visit_expr_mut(left);
+            visitor.visit_expr_mut(right);
+        }
+        Expr::If {
+            condition,
+            consequence,
+            alternative,
+            ..
+        } => {
+            visitor.visit_expr_mut(condition);
+            visitor.visit_block_mut(consequence);
+            visitor.visit_block_mut(alternative);
+        }
+        Expr::Function { body, .. } | Expr::Block(body) => visitor.visit_block_mut(body),
+        Expr::Call { function, arguments } => {
+            visitor.visit_expr_mut(function);
+            for argument in arguments {
+                visitor.visit_expr_mut(argument);
+            }
+        }
+        Expr::Array(elements) => {
+            for element in elements {
+                visitor.visit_expr_mut(element);
+            }
+        }
+        Expr::Hash(pairs) => {
+            for (key, value) in pairs {
+                visitor.visit_expr_mut(key);
+                visitor.visit_expr_mut(value);
+            }
+        }
+        Expr::Index { left, index } => {
+            visitor.visit_expr_mut(left);
+            visitor.visit_expr_mut(index);
+        }
+        Expr::Assign { target, value, .. } => {
+            visitor.visit_expr_mut(target);
+            visitor.visit_expr_mut(value);
+        }
+        Expr::Const(_) | Expr::String(_) | Expr::Boolean(_) | Expr::Ident(_) | Expr::Local { .. } | Expr::Unknown => {}
+    }
+}
+
+pub trait Fold {
+    fn fold_block(&mut self, block: Vec<Statement>) -> Vec<Statement> {
+        fold_block(self, block)
+    }
+
+    fn fold_statement(&mut self, statement: Statement) -> Statement {
+        fold_statement(self, statement)
+    }
+
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        fold_expr(self, expr)
+    }
+}
+
+pub fn fold_block<F: Fold + ?Sized>(folder: &mut F, block: Vec<Statement>) -> Vec<Statement> {
+    block
+        .into_iter()
+        .map(|statement| folder.fold_statement(statement))
+        .collect()
+}
+
+pub fn fold_statement<F: Fold + ?Sized>(folder: &mut F, statement: Statement) -> Statement {
+    match statement {
+        Statement::Let { ident, value } => Statement::Let {
+            ident,
+            value: Box::new(folder.fold_expr(*value)),
+        },
+        Statement::Expression(value) => Statement::Expression(Box::new(folder.fold_expr(*value))),
+        Statement::Return { value } => Statement::Return {
+            value: Box::new(folder.fold_expr(*value)),
+        },
+        Statement::While { condition, body } => {
+            let condition = Box::new(folder.fold_expr(*condition));
+            Statement::While {
+                condition,
+                body: folder.fold_block(body),
+            }
+        }
+        Statement::For {
+            variable,
+            iterable,
+            body,
+        } => {
+            let iterable = Box::new(folder.fold_expr(*iterable));
+            Statement::For {
+                variable,
+                iterable,
+                body: folder.fold_block(body),
+            }
+        }
+        Statement::Break(location) => Statement::Break(location),
+        Statement::Continue(location) => Statement::Continue(location),
+        Statement::Empty => Statement::Empty,
+    }
+}
+
+pub fn fold_expr<F: Fold + ?Sized>(folder: &mut F, expr: Expr) -> Expr {
+    match expr {
+        Expr::Prefix { prefix, value } => Expr::Prefix {
+            prefix,
+            value: Box::new(folder.fold_expr(*value)),
+        },
+        Expr::Infix {
+            left,
+            operator,
+            right,
+            location,
+        } => {
+            let left = Box::new(folder.fold_expr(*left));
+            Expr::Infix {
+                left,
+                operator,
+                right: Box::new(folder.fold_expr(*right)),
+                location,
+            }
+        }
+        Expr::If {
+            condition,
+            consequence,
+            alternative,
+            location,
+        } => {
+            let condition = Box::new(folder.fold_expr(*condition));
+            let consequence = folder.fold_block(consequence);
+            Expr::If {
+                condition,
+                consequence,
+                alternative: folder.fold_block(alternative),
+                location,
+            }
+        }
+        Expr::Function { parameters, body } => Expr::Function {
+            parameters,
+            body: folder.fold_block(body),
+        },
+        Expr::Block(body) => Expr::Block(folder.fold_block(body)),
+        Expr::Call { function, arguments } => {
+            let function = Box::new(folder.fold_expr(*function));
+            Expr::Call {
+                function,
+                arguments: arguments
+                    .into_iter()
+                    .map(|argument| folder.fold_expr(argument))
+                    .collect(),
+            }
+        }
+        Expr::Array(elements) => Expr::Array(elements.into_iter().map(|element| folder.fold_expr(element)).collect()),
+        Expr::Hash(pairs) => Expr::Hash(
+            pairs
+                .into_iter()
+                .map(|(key, value)| {
+                    let key = folder.fold_expr(key);
+                    (key, folder.fold_expr(value))
+                })
+                .collect(),
+        ),
+        Expr::Index { left, index } => {
+            let left = Box::new(folder.fold_expr(*left));
+            Expr::Index {
+                left,
+                index: Box::new(folder.fold_expr(*index)),
+            }
+        }
+        Expr::Assign {
+            target,
+            operator,
+            value,
+        } => {
+            let target = Box::new(folder.fold_expr(*target));
+            Expr::Assign {
+                target,
+                operator,
+                value: Box::new(folder.fold_expr(*value)),
+            }
+        }
+        expr @ (Expr::Const(_)
+        | Expr::String(_)
+        | Expr::Boolean(_)
+        | Expr::Ident(_)
+        | Expr::Local { .. }
+        | Expr::Unknown) => expr,
+    }
+}
+
+/// Drops the source locations of `program`, so that trees parsed from sources that only differ
+/// in layout compare equal.
+pub fn strip_locations(program: Vec<Statement>) -> Vec<Statement> {
+    StripLocations.fold_block(program)
+}
+
+struct StripLocations;
+
+impl Fold for StripLocations {
+    fn fold_statement(&mut self, statement: Statement) -> Statement {
+        match fold_statement(self, statement) {
+            Statement::Let { ident, value } => Statement::Let {
+                ident: Ident::new(&ident.name),
+                value,
+            },
+            Statement::For {
+                variable,
+                iterable,
+                body,
+            } => Statement::For {
+                variable: Ident::new(&variable.name),
+                iterable,
+                body,
+            },
+            Statement::Break(_) => Statement::Break(Location::default()),
+            Statement::Continue(_) => Statement::Continue(Location::default()),
+            statement => statement,
+        }
+    }
+
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        match fold_expr(self, expr) {
+            Expr::Ident(ident) => Expr::Ident(Ident::new(&ident.name)),
+            Expr::Local { name, depth, slot } => Expr::Local {
+                name: Ident::new(&name.name),
+                depth,
+                slot,
+            },
+            Expr::Function { parameters, body } => Expr::Function {
+                parameters: parameters.iter().map(|parameter| Ident::new(&parameter.name)).collect(),
+                body,
+            },
+            Expr::Infix {
+                left, operator, right, ..
+            } => Expr::Infix {
+                left,
+                operator,
+                right,
+                location: Location::default(),
+            },
+            Expr::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => Expr::If {
+                condition,
+                consequence,
+                alternative,
+                location: Location::default(),
+            },
+            expr => expr,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast::Token, parse_program};
+
+    #[test]
+    fn visits_in_source_order() {
+        struct Identifiers(Vec<String>);
+        impl Visitor for Identifiers {
+            fn visit_expr(&mut self, expr: &Expr) {
+                if let Expr::Ident(ident) = expr {
+                    self.0.push(ident.name.clone());
+                }
+                walk_expr(self, expr);
+            }
+        }
+
+        let program = parse_program("let f = fn(x) { if (a) { b } else { c(d, e) } }; return g + -h;").unwrap();
+        let mut identifiers = Identifiers(Vec::new());
+        identifiers.visit_block(&program);
+        assert_eq!(identifiers.0, vec!["a", "b", "c", "d", "e", "g", "h"]);
+    }
+
+    #[test]
+    fn visits_mutably() {
+        struct Rename;
+        impl VisitorMut for Rename {
+            fn visit_expr_mut(&mut self, expr: &mut Expr) {
+                if let Expr::Ident(ident) = expr {
+                    ident.name.make_ascii_uppercase();
+                }
+                walk_expr_mut(self, expr);
+            }
+        }
+
+        let mut program = parse_program("f(x, fn() { y })").unwrap();
+        Rename.visit_block_mut(&mut program);
+        assert_eq!(program, parse_program("F(X, fn() { Y })").unwrap());
+    }
+
+    #[test]
+    fn folds_constants() {
+        struct ConstantFolder;
+        impl Fold for ConstantFolder {
+            fn fold_expr(&mut self, expr: Expr) -> Expr {
+                match fold_expr(self, expr) {
+                    Expr::Infix {
+                        left,
+                        operator: Token::Plus,
+                        right,
+                        ..
+                    } => match (*left, *right) {
+                        (Expr::Const(a), Expr::Const(b)) => Expr::Const(a + b),
+                        (left, right) => Expr::Infix {
+                            left: Box::new(left),
+                            operator: Token::Plus,
+                            right: Box::new(right),
+                            location: Location::default(),
+                        },
+                    },
+                    expr => expr,
+                }
+            }
+        }
+
+        let program = parse_program("let x = 1 + 2 + 3; fn() { x + (4 + 5) }").unwrap();
+        let folded = ConstantFolder.fold_block(program);
+        assert_eq!(
+            strip_locations(folded),
+            strip_locations(parse_program("let x = 6; fn() { x + 9 }").unwrap())
+        );
+    }
+
+    #[test]
+    fn strips_locations() {
+        let program = parse_program("for (i in xs) { let y = fn(a) { a }; break; }").unwrap();
+        let spaced = parse_program("for (i in xs) {\n    let y = fn(a) { a };\n    break;\n}").unwrap();
+        assert_ne!(program, spaced);
+        assert_eq!(strip_locations(program), strip_locations(spaced));
+    }
+}
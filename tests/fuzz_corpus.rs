@@ -0,0 +1,86 @@
+//! Replays the seeds in `fuzz/corpus` through the checks of the matching fuzz targets, so the
+//! crashes fixed there stay fixed without a nightly toolchain and `cargo fuzz`.
+use amp::{eval::Evaluator, formatter, parse_program, tokenize, visit::strip_locations, AmpError};
+use std::{fs, path::Path};
+
+/// The seeds of `target`, by file name.
+fn seeds(target: &str) -> Vec<(String, Vec<u8>)> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus").join(target);
+    let mut seeds: Vec<(String, Vec<u8>)> = fs::read_dir(&dir)
+        .expect("fuzz corpus exists")
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, fs::read(&path).unwrap())
+        })
+        .collect();
+    assert!(!seeds.is_empty(), "no seeds for {}", target);
+    seeds.sort();
+    seeds
+}
+
+#[test]
+fn lexer_seeds_have_ordered_spans() {
+    for (name, data) in seeds("lexer") {
+        let source = String::from_utf8_lossy(&data);
+        let mut end = 0;
+        for (_, span) in tokenize(&source) {
+            assert!(span.start >= end && span.start < span.end, "{}: {:?}", name, span);
+            assert!(source.get(span.start..span.end).is_some(), "{}: {:?}", name, span);
+            end = span.end;
+        }
+    }
+}
+
+#[test]
+fn parser_seeds_do_not_panic() {
+    for (_, data) in seeds("parser") {
+        if let Ok(source) = std::str::from_utf8(&data) {
+            let _ = parse_program(source);
+        }
+    }
+}
+
+#[test]
+fn roundtrip_seeds_format_to_the_same_tree() {
+    for (name, data) in seeds("roundtrip") {
+        let source = match std::str::from_utf8(&data) {
+            Ok(source) => source,
+            Err(_) => continue,
+        };
+        let program = match parse_program(source) {
+            Ok(program) => program,
+            Err(_) => continue,
+        };
+        let formatted = formatter::format(source).unwrap_or_else(|e| panic!("{}: failed to format: {}", name, e));
+        assert_eq!(
+            parse_program(&formatted).map(strip_locations),
+            Ok(strip_locations(program)),
+            "{}: formatted as {:?}",
+            name,
+            formatted
+        );
+    }
+}
+
+#[test]
+fn long_chains_are_rejected() {
+    // Found by the parser and round-trip targets: flat chains used to build trees deep enough to
+    // overflow the stack when they were dropped, resolved or evaluated.
+    for target in ["parser", "roundtrip"].iter() {
+        for name in ["long_infix_chain.amp", "long_index_chain.amp"].iter() {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("fuzz/corpus")
+                .join(target)
+                .join(name);
+            let source = fs::read_to_string(path).unwrap();
+            assert!(
+                matches!(parse_program(&source), Err(AmpError::NestingTooDeep { .. })),
+                "{}/{}",
+                target,
+                name
+            );
+            assert!(Evaluator::new().eval(&source).is_err());
+        }
+    }
+}
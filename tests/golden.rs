@@ -0,0 +1,113 @@
+//! Golden-file tests.
+//!
+//! Every `tests/golden/*.amp` program is tokenized, parsed and run, and the results are compared
+//! with the snapshots next to it: `.tokens` for the tokens, `.ast` for the s-expression of the
+//! tree, `.out` for what the program printed and `.err` for parse, resolution or runtime errors. A
+//! snapshot that would be empty must not exist. Run with `AMP_BLESS=1` to write the current
+//! results instead of comparing them.
+use amp::{dump, eval::Evaluator, parse_program, resolver, tokenize};
+use std::{
+    cell::RefCell,
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+const BLESS_VAR: &str = "AMP_BLESS";
+
+/// Collects everything the program prints.
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Results of running `source`, keyed by snapshot extension.
+fn snapshots(source: &str) -> Vec<(&'static str, String)> {
+    let mut tokens = String::new();
+    for (token, span) in tokenize(source) {
+        tokens.push_str(&format!("{}:{}\t{:?}\n", span.line, span.column, token));
+    }
+
+    let mut ast = String::new();
+    let mut out = String::new();
+    let mut err = String::new();
+    let errors: Vec<String> = resolver::diagnostics(source, &[])
+        .into_iter()
+        .filter(|diagnostic| diagnostic.is_error())
+        .map(|diagnostic| format!("{}\n", diagnostic))
+        .collect();
+    if let Ok(program) = parse_program(source) {
+        ast = dump::sexpr(&program) + "\n";
+    }
+    if errors.is_empty() {
+        let output = Output::default();
+        let result = Evaluator::with_output(Box::new(output.clone())).eval(source);
+        out = String::from_utf8_lossy(&output.0.borrow()).into_owned();
+        if let Err(e) = result {
+            err = format!("runtime error: {}\n", e);
+        }
+    } else {
+        err = errors.concat();
+    }
+
+    vec![("tokens", tokens), ("ast", ast), ("out", out), ("err", err)]
+}
+
+fn golden_files() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir)
+        .expect("tests/golden exists")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "amp"))
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn golden() {
+    let bless = env::var_os(BLESS_VAR).is_some();
+    let files = golden_files();
+    assert!(!files.is_empty(), "no golden files found");
+
+    let mut failures = Vec::new();
+    for file in files {
+        let source = fs::read_to_string(&file).unwrap();
+        for (extension, actual) in snapshots(&source) {
+            let snapshot = file.with_extension(extension);
+            if bless {
+                if actual.is_empty() {
+                    let _ = fs::remove_file(&snapshot);
+                } else {
+                    fs::write(&snapshot, &actual).unwrap();
+                }
+                continue;
+            }
+            let expected = fs::read_to_string(&snapshot).unwrap_or_default();
+            if expected != actual {
+                failures.push(format!(
+                    "{} differs\n--- expected\n{}--- actual\n{}",
+                    snapshot.display(),
+                    expected,
+                    actual
+                ));
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{}\nrun with {}=1 to accept the new output",
+        failures.join("\n"),
+        BLESS_VAR
+    );
+}
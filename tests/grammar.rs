@@ -0,0 +1,128 @@
+//! Checks that docs/grammar.ebnf agrees with the implementation.
+//!
+//! Every rule the grammar refers to must be defined, every quoted terminal must be a single token
+//! of the lexer, every token of the golden programs must appear in the grammar and the `ok:` and
+//! `error:` examples must parse or fail to parse.
+use amp::{ast::Token, parse_program, tokenize};
+use std::{collections::BTreeSet, fs, path::Path};
+
+const GRAMMAR: &str = "docs/grammar.ebnf";
+
+struct Grammar {
+    rules: BTreeSet<String>,
+    references: BTreeSet<String>,
+    terminals: BTreeSet<String>,
+    examples: Vec<(bool, String)>,
+}
+
+/// Splits the grammar into rules, the rule names and terminals their bodies use and the examples
+/// in comments.
+fn read_grammar() -> Grammar {
+    let text = fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join(GRAMMAR)).unwrap();
+    let mut grammar = Grammar {
+        rules: BTreeSet::new(),
+        references: BTreeSet::new(),
+        terminals: BTreeSet::new(),
+        examples: Vec::new(),
+    };
+
+    let mut rest = text.as_str();
+    let mut body = String::new();
+    while let Some(ch) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("(*") {
+            let end = after.find("*)").expect("unterminated comment");
+            let comment = after[..end].trim();
+            if let Some(example) = comment.strip_prefix("ok:") {
+                grammar.examples.push((true, example.trim().to_string()));
+            } else if let Some(example) = comment.strip_prefix("error:") {
+                grammar.examples.push((false, example.trim().to_string()));
+            }
+            rest = &after[end + 2..];
+        } else if ch == '"' || ch == '?' {
+            let end = rest[1..].find(ch).expect("unterminated terminal") + 1;
+            if ch == '"' {
+                grammar.terminals.insert(rest[1..end].to_string());
+            }
+            body.push(' ');
+            rest = &rest[end + 1..];
+        } else {
+            body.push(ch);
+            rest = &rest[ch.len_utf8()..];
+        }
+    }
+
+    for rule in body.split(';') {
+        if rule.trim().is_empty() {
+            continue;
+        }
+        let (name, definition) = rule.split_once('=').expect("rule without `=`");
+        grammar.rules.insert(normalize(name));
+        for reference in definition.split(|ch| ",|[]{}()".contains(ch)) {
+            if !reference.trim().is_empty() {
+                grammar.references.insert(normalize(reference));
+            }
+        }
+    }
+    grammar
+}
+
+fn normalize(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[test]
+fn rules_are_defined_and_used() {
+    let grammar = read_grammar();
+    let undefined: Vec<_> = grammar.references.difference(&grammar.rules).collect();
+    assert!(undefined.is_empty(), "undefined rules: {:?}", undefined);
+    let mut unused: Vec<_> = grammar.rules.difference(&grammar.references).collect();
+    unused.retain(|rule| rule.as_str() != "program");
+    assert!(unused.is_empty(), "unused rules: {:?}", unused);
+}
+
+#[test]
+fn terminals_are_tokens() {
+    for terminal in read_grammar().terminals {
+        let tokens = tokenize(&terminal);
+        assert!(
+            tokens.len() == 1 && !matches!(tokens[0].0, Token::Invalid(_) | Token::Identifier(_)),
+            "{:?} is not a single token",
+            terminal
+        );
+    }
+}
+
+#[test]
+fn golden_tokens_are_in_the_grammar() {
+    let terminals = read_grammar().terminals;
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let programs = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "amp"));
+    for path in programs {
+        for (token, span) in tokenize(&fs::read_to_string(&path).unwrap()) {
+            if let Token::Integer(_) | Token::String(_) | Token::Identifier(_) | Token::Comment(_) = token {
+                continue;
+            }
+            assert!(
+                terminals.contains(&token.to_string()),
+                "{}:{}:{}: {:?} is missing from the grammar",
+                path.display(),
+                span.line,
+                span.column,
+                token
+            );
+        }
+    }
+}
+
+#[test]
+fn examples_match_the_parser() {
+    let examples = read_grammar().examples;
+    assert!(!examples.is_empty());
+    for (valid, example) in examples {
+        let result = parse_program(&example);
+        assert_eq!(result.is_ok(), valid, "{}: {:?}", example, result);
+    }
+}